@@ -0,0 +1,194 @@
+#[macro_use]
+extern crate criterion;
+extern crate concurrent_stack;
+
+use concurrent_stack::{ConcurrentStack, RetryPolicy};
+use criterion::{black_box, BenchmarkId, Criterion, Throughput};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const OPS: u64 = 10000;
+
+// Push then pop `OPS` values on one thread.
+fn single_thread(c: &mut Criterion) {
+    let mut group = c.benchmark_group("single_thread");
+    group.throughput(Throughput::Elements(OPS * 2));
+    group.bench_function("push_pop", |b| {
+        let stack = ConcurrentStack::new();
+        b.iter(|| {
+            for i in 0..OPS {
+                stack.push(black_box(i));
+            }
+            let mut n = 0;
+            while stack.pop().is_some() {
+                n += 1;
+            }
+            assert_eq!(n, OPS);
+        })
+    });
+    group.finish();
+}
+
+// Every thread pushes and pops `OPS` values in turns on one shared stack.
+fn contended(c: &mut Criterion) {
+    let mut group = c.benchmark_group("contended");
+    for &threads in &[2, 4, 8, 16] {
+        group.throughput(Throughput::Elements(OPS * 2 * threads));
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &threads, |b, &threads| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::new(0, 0);
+                for _ in 0..iters {
+                    total += run_contended(&Arc::new(ConcurrentStack::new()), threads);
+                }
+                total
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("elimination", threads), &threads, |b, &threads| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::new(0, 0);
+                for _ in 0..iters {
+                    total += run_contended(&Arc::new(ConcurrentStack::with_elimination()), threads);
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+// Retries waiting as the default backoff says against retries right away.
+fn backoff(c: &mut Criterion) {
+    struct NoBackoff;
+
+    impl RetryPolicy for NoBackoff {
+        fn retry(&self, _attempt: u32) {}
+    }
+
+    let mut group = c.benchmark_group("backoff");
+    for &threads in &[4, 16] {
+        group.throughput(Throughput::Elements(OPS * 2 * threads));
+        group.bench_with_input(BenchmarkId::new("backoff", threads), &threads, |b, &threads| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::new(0, 0);
+                for _ in 0..iters {
+                    total += run_contended(&Arc::new(ConcurrentStack::new()), threads);
+                }
+                total
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("no_backoff", threads), &threads, |b, &threads| {
+            b.iter_custom(|iters| {
+                let mut total = Duration::new(0, 0);
+                for _ in 0..iters {
+                    let stack = ConcurrentStack::with_policy(NoBackoff);
+                    total += run_contended(&Arc::new(stack), threads);
+                }
+                total
+            })
+        });
+    }
+    group.finish();
+}
+
+fn run_contended(stack: &Arc<ConcurrentStack<u64>>, threads: u64) -> Duration {
+    let barrier = Arc::new(Barrier::new(threads as usize + 1));
+    let workers = (0..threads)
+                      .map(|_| {
+                          let stack = stack.clone();
+                          let barrier = barrier.clone();
+                          thread::spawn(move || {
+                              let mut popped = 0;
+                              barrier.wait();
+                              for i in 0..OPS {
+                                  stack.push(i);
+                                  if stack.pop().is_some() {
+                                      popped += 1;
+                                  }
+                              }
+                              popped
+                          })
+                      })
+                      .collect::<Vec<_>>();
+    barrier.wait();
+    let start = Instant::now();
+    let popped: u64 = workers.into_iter().map(|t| t.join().unwrap()).sum();
+    let elapsed = start.elapsed();
+    // Every value pushed is popped by a worker or still on the stack.
+    assert_eq!(popped + stack.pop_all().len() as u64, OPS * threads);
+    elapsed
+}
+
+// Batches pushed and popped by 8 threads, which hits `top`, the trash and both counters.
+// The layout is fixed at build time, so the unpadded numbers come from a second run:
+//
+//     cargo bench -- padding && cargo bench --features no-padding -- padding
+fn padding(c: &mut Criterion) {
+    const THREADS: u64 = 8;
+    const BATCH: u64 = 8;
+
+    let layout = if cfg!(feature = "no-padding") { "unpadded" } else { "padded" };
+    let mut group = c.benchmark_group("padding");
+    group.throughput(Throughput::Elements(OPS * 2 * THREADS));
+    group.bench_function(layout, |b| {
+        b.iter_custom(|iters| {
+            let mut total = Duration::new(0, 0);
+            for _ in 0..iters {
+                let stack = Arc::new(ConcurrentStack::new());
+                let barrier = Arc::new(Barrier::new(THREADS as usize + 1));
+                let workers = (0..THREADS)
+                                  .map(|_| {
+                                      let stack = stack.clone();
+                                      let barrier = barrier.clone();
+                                      thread::spawn(move || {
+                                          barrier.wait();
+                                          for i in 0..OPS / BATCH {
+                                              stack.push_iter((0..BATCH).map(|_| i));
+                                              for _ in 0..BATCH {
+                                                  assert!(stack.pop().is_some());
+                                              }
+                                          }
+                                      })
+                                  })
+                                  .collect::<Vec<_>>();
+                barrier.wait();
+                let start = Instant::now();
+                for t in workers {
+                    t.join().unwrap();
+                }
+                total += start.elapsed();
+                assert!(stack.is_empty());
+            }
+            total
+        })
+    });
+    group.finish();
+}
+
+// Pushes that reuse popped nodes against pushes that always allocate.
+fn recycling(c: &mut Criterion) {
+    let mut group = c.benchmark_group("recycling");
+    group.throughput(Throughput::Elements(OPS));
+    group.bench_function("recycle", |b| {
+        let stack = ConcurrentStack::with_preallocated(OPS as usize);
+        b.iter(|| {
+            for i in 0..OPS {
+                stack.push(black_box(i));
+            }
+            assert_eq!(stack.pop_n(OPS as usize).len() as u64, OPS);
+        })
+    });
+    group.bench_function("allocate", |b| {
+        let stack = ConcurrentStack::with_trash_limit(0);
+        b.iter(|| {
+            for i in 0..OPS {
+                stack.push(black_box(i));
+            }
+            assert_eq!(stack.pop_n(OPS as usize).len() as u64, OPS);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, single_thread, contended, backoff, padding, recycling);
+criterion_main!(benches);
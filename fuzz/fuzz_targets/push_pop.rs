@@ -0,0 +1,127 @@
+//! Runs a schedule of stack operations read from the input on up to 4 threads, then
+//! checks that every value pushed was either popped exactly once or is still on the
+//! stack. With a single thread the stack must also pop exactly what a `Vec` would.
+
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate concurrent_stack;
+
+use concurrent_stack::ConcurrentStack;
+use std::sync::{Arc, Barrier};
+use std::thread;
+
+#[derive(Default)]
+struct Log {
+    pushed: Vec<u32>,
+    popped: Vec<u32>,
+}
+
+// One operation per byte pair: the low bits of the first byte pick it, the second byte
+// is its value or count. Values are tagged with the thread, so they are unique per
+// thread and any duplicate coming out is a real one.
+fn run(stack: &ConcurrentStack<u32>, thread: u32, ops: &[u8], model: Option<&mut Vec<u32>>) -> Log {
+    let mut log = Log::default();
+    let mut model = model;
+    let mut next = 0u32;
+    let mut value = || {
+        next += 1;
+        thread << 24 | next
+    };
+    for op in ops.chunks(2) {
+        let arg = op.get(1).cloned().unwrap_or(0) as usize;
+        match op[0] % 6 {
+            0 => {
+                let v = value();
+                stack.push(v);
+                log.pushed.push(v);
+                if let Some(m) = model.as_mut() {
+                    m.push(v);
+                }
+            }
+            1 => {
+                let v = stack.pop();
+                if let Some(m) = model.as_mut() {
+                    assert_eq!(v, m.pop());
+                }
+                log.popped.extend(v);
+            }
+            2 => {
+                let empty = stack.is_empty();
+                if let Some(m) = model.as_mut() {
+                    assert_eq!(empty, m.is_empty());
+                    assert_eq!(stack.len(), m.len());
+                }
+            }
+            3 => {
+                let values = (0..arg % 8).map(|_| value()).collect::<Vec<_>>();
+                stack.push_iter(values.iter().cloned());
+                if let Some(m) = model.as_mut() {
+                    m.extend_from_slice(&values);
+                }
+                log.pushed.extend(values);
+            }
+            4 => {
+                let values = stack.pop_n(arg % 8);
+                if let Some(m) = model.as_mut() {
+                    for &v in &values {
+                        assert_eq!(Some(v), m.pop());
+                    }
+                    assert!(values.len() == arg % 8 || m.is_empty());
+                }
+                log.popped.extend(values);
+            }
+            _ => {
+                if let Some(v) = stack.peek() {
+                    assert!(v >> 24 < 4);
+                    if let Some(m) = model.as_mut() {
+                        assert_eq!(Some(&v), m.last());
+                    }
+                }
+            }
+        }
+    }
+    log
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.is_empty() {
+        return;
+    }
+    let threads = (data[0] % 4 + 1) as usize;
+    let stack = Arc::new(ConcurrentStack::new());
+    let mut logs = Vec::new();
+    if threads == 1 {
+        let mut model = Vec::new();
+        logs.push(run(&stack, 0, &data[1..], Some(&mut model)));
+        assert_eq!(stack.len(), model.len());
+    } else {
+        // Deal the operations out round robin, so every thread gets a share.
+        let barrier = Arc::new(Barrier::new(threads));
+        let workers = (0..threads)
+                          .map(|t| {
+                              let ops = data[1..]
+                                            .chunks(2)
+                                            .skip(t)
+                                            .step_by(threads)
+                                            .flat_map(|op| op.iter().cloned())
+                                            .collect::<Vec<_>>();
+                              let stack = stack.clone();
+                              let barrier = barrier.clone();
+                              thread::spawn(move || {
+                                  barrier.wait();
+                                  run(&stack, t as u32, &ops, None)
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        logs.extend(workers.into_iter().map(|w| w.join().unwrap()));
+    }
+
+    let mut pushed = logs.iter().flat_map(|l| l.pushed.iter().cloned()).collect::<Vec<_>>();
+    let mut out = logs.iter().flat_map(|l| l.popped.iter().cloned()).collect::<Vec<_>>();
+    out.extend(stack.pop_all());
+    pushed.sort();
+    out.sort();
+    assert_eq!(pushed, out);
+    assert!(stack.is_empty());
+});
@@ -0,0 +1,118 @@
+//! A stack whose consumers can await values from async tasks, enabled by the `std`
+//! feature.
+//!
+//! Works with any executor: a task that finds the stack empty leaves its `Waker`
+//! behind, and the next push wakes it.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::task::{Context, Poll, Waker};
+
+use super::ConcurrentStack;
+
+/// A `ConcurrentStack` with pops that can be awaited.
+///
+/// Push and pop stay lock free. Only a task that finds the stack empty takes a lock to
+/// register its waker, and pushes take the lock to wake the registered tasks only while
+/// there are some.
+pub struct AsyncStack<T> {
+    stack: ConcurrentStack<T>,
+    wakers: Mutex<Vec<Waker>>,
+    waiters: AtomicUsize,
+}
+
+impl<T> AsyncStack<T> {
+    pub fn new() -> Self {
+        AsyncStack {
+            stack: ConcurrentStack::new(),
+            wakers: Mutex::new(Vec::new()),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    fn guard(&self) -> MutexGuard<'_, Vec<Waker>> {
+        self.wakers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Push a value on the top of stack, waking the tasks waiting for one.
+    ///
+    /// All of them are woken, those that lose the race for the value register again.
+    pub fn push(&self, raw: T) {
+        self.stack.push(raw);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let wakers = {
+                let mut guard = self.guard();
+                self.waiters.store(0, Ordering::SeqCst);
+                core::mem::take(&mut *guard)
+            };
+            for w in wakers {
+                w.wake();
+            }
+        }
+    }
+
+    /// Pop a value from the top of stack, if no available， return None.
+    pub fn pop(&self) -> Option<T> {
+        self.stack.pop()
+    }
+
+    /// Pop a value from the top of stack, or register the waker of `cx` to be woken by
+    /// the next push and return `Poll::Pending`.
+    pub fn poll_pop(&self, cx: &mut Context<'_>) -> Poll<T> {
+        if let Some(v) = self.stack.pop() {
+            return Poll::Ready(v);
+        }
+        {
+            let mut guard = self.guard();
+            if !guard.iter().any(|w| w.will_wake(cx.waker())) {
+                guard.push(cx.waker().clone());
+            }
+            self.waiters.store(guard.len(), Ordering::SeqCst);
+        }
+        // Checked after registering, so a push either shows up here or sees the waker
+        // and wakes it. In the first case the waker stays registered and is woken for
+        // nothing by a later push.
+        match self.stack.pop() {
+            Some(v) => Poll::Ready(v),
+            None => Poll::Pending,
+        }
+    }
+
+    /// Pop a value from the top of stack, waiting for one if the stack is empty.
+    ///
+    /// The returned future resolves through `poll_pop`.
+    pub fn pop_async(&self) -> PopFuture<'_, T> {
+        PopFuture { stack: self }
+    }
+
+    /// Number of values on the stack, see `ConcurrentStack::len`.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Check if stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+impl<T> Default for AsyncStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A pop waiting for a value, see `AsyncStack::pop_async`.
+pub struct PopFuture<'a, T: 'a> {
+    stack: &'a AsyncStack<T>,
+}
+
+impl<'a, T> Future for PopFuture<'a, T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.stack.poll_pop(cx)
+    }
+}
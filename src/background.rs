@@ -0,0 +1,60 @@
+//! A thread doing periodic work for a stack, enabled by the `std` feature.
+//!
+//! Used by `ConcurrentStack::with_background_reclaim`. The thread only holds what the
+//! work closure captures, the stack itself through a `Weak`, so it never keeps the
+//! stack alive, and the stack stops and joins it when dropped.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+struct Shutdown {
+    stopped: Mutex<bool>,
+    wake: Condvar,
+}
+
+/// The handle of the thread, stopping it when dropped.
+pub struct Worker {
+    shutdown: Arc<Shutdown>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Spawn a thread calling `tick` every `interval` until the worker is dropped.
+    pub fn spawn<F: FnMut() + Send + 'static>(interval: Duration, mut tick: F) -> Self {
+        let shutdown = Arc::new(Shutdown { stopped: Mutex::new(false), wake: Condvar::new() });
+        let shared = shutdown.clone();
+        let thread = thread::spawn(move || {
+            let mut stopped = shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+            loop {
+                stopped = shared.wake
+                                .wait_timeout(stopped, interval)
+                                .unwrap_or_else(|e| e.into_inner())
+                                .0;
+                if *stopped {
+                    return;
+                }
+                // Not holding the lock, `tick` may drop the last handle to the stack and
+                // with it the worker.
+                drop(stopped);
+                tick();
+                stopped = shared.stopped.lock().unwrap_or_else(|e| e.into_inner());
+            }
+        });
+        Worker { shutdown, thread: Some(thread) }
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        *self.shutdown.stopped.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.shutdown.wake.notify_all();
+        if let Some(thread) = self.thread.take() {
+            // Dropped by the thread itself when `tick` let go of the stack last, which
+            // then stops on its own right after.
+            if thread.thread().id() != thread::current().id() {
+                let _ = thread.join();
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+//! Exponential backoff for CAS retry loops.
+
+#[cfg(feature = "std")]
+use std::thread;
+use policy::RetryPolicy;
+use sync::spin_loop;
+
+// Spin 2^SPIN_LIMIT times at most before starting to yield.
+const SPIN_LIMIT: u32 = 6;
+
+/// Backoff state of one operation, created fresh for every push or pop.
+pub struct Backoff<'a> {
+    step: u32,
+    policy: Option<&'a dyn RetryPolicy>,
+}
+
+impl<'a> Backoff<'a> {
+    /// Wait as `policy` says, or with the built-in spin-then-yield if there is none.
+    pub fn new(policy: Option<&'a dyn RetryPolicy>) -> Self {
+        Backoff { step: 0, policy }
+    }
+
+    /// Called after a failed CAS: spin for a growing number of iterations, then give
+    /// the CPU to another thread once spinning stops paying off. Without `std` there is
+    /// no scheduler to yield to, so it keeps spinning at the longest length.
+    pub fn snooze(&mut self) {
+        // Under loom every spin is a yield, one is enough to let the others run.
+        if cfg!(loom) {
+            spin_loop();
+            return;
+        }
+        if let Some(policy) = self.policy {
+            policy.retry(self.step);
+            self.step = self.step.saturating_add(1);
+            return;
+        }
+        if self.step <= SPIN_LIMIT {
+            for _ in 0..1 << self.step {
+                spin_loop();
+            }
+            self.step += 1;
+        } else {
+            Self::yield_now();
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn yield_now() {
+        thread::yield_now();
+    }
+
+    #[cfg(not(feature = "std"))]
+    fn yield_now() {
+        for _ in 0..1 << SPIN_LIMIT {
+            spin_loop();
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! Stacks whose consumers can wait for values, or producers for room, enabled by the
+//! `std` feature.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use super::ConcurrentStack;
+
+/// A `ConcurrentStack` with blocking pops.
+///
+/// Push and pop stay lock free. Only a consumer that finds the stack empty takes a lock
+/// and parks on a condition variable, and pushes take the lock to wake it only while
+/// someone is parked.
+pub struct BlockingStack<T> {
+    stack: ConcurrentStack<T>,
+    lock: Mutex<()>,
+    ready: Condvar,
+    waiters: AtomicUsize,
+}
+
+impl<T> BlockingStack<T> {
+    pub fn new() -> Self {
+        BlockingStack {
+            stack: ConcurrentStack::new(),
+            lock: Mutex::new(()),
+            ready: Condvar::new(),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    fn guard(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Push a value on the top of stack, waking a waiting consumer.
+    pub fn push(&self, raw: T) {
+        self.stack.push(raw);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            let _guard = self.guard();
+            self.ready.notify_one();
+        }
+    }
+
+    /// Pop a value from the top of stack, if no available， return None.
+    pub fn pop(&self) -> Option<T> {
+        self.stack.pop()
+    }
+
+    /// Pop a value from the top of stack, waiting for one if the stack is empty.
+    pub fn pop_blocking(&self) -> T {
+        match self.pop_until(None) {
+            Some(v) => v,
+            None => unreachable!(),
+        }
+    }
+
+    /// Pop a value from the top of stack, waiting at most `timeout` for one.
+    pub fn pop_timeout(&self, timeout: Duration) -> Option<T> {
+        self.pop_until(Some(Instant::now() + timeout))
+    }
+
+    fn pop_until(&self, deadline: Option<Instant>) -> Option<T> {
+        if let Some(v) = self.stack.pop() {
+            return Some(v);
+        }
+        let mut guard = self.guard();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let v = loop {
+            // Checked after announcing the wait, so a push either shows up here or
+            // sees the waiter and wakes us.
+            if let Some(v) = self.stack.pop() {
+                break Some(v);
+            }
+            guard = match deadline {
+                None => self.ready.wait(guard).unwrap_or_else(|e| e.into_inner()),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        break None;
+                    }
+                    self.ready
+                        .wait_timeout(guard, deadline - now)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+            };
+        };
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        v
+    }
+
+    /// Number of values on the stack, see `ConcurrentStack::len`.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Check if stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
+
+impl<T> Default for BlockingStack<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A `ConcurrentStack` of bounded capacity whose pushes wait for room.
+///
+/// Pushes that find room and all pops stay lock free. Only a producer that finds the
+/// stack full takes a lock and parks on a condition variable, and pops take the lock to
+/// wake it only while someone is parked.
+pub struct BoundedBlockingStack<T> {
+    stack: ConcurrentStack<T>,
+    lock: Mutex<()>,
+    room: Condvar,
+    waiters: AtomicUsize,
+}
+
+impl<T> BoundedBlockingStack<T> {
+    /// Create a stack holding at most `capacity` values.
+    pub fn new(capacity: usize) -> Self {
+        BoundedBlockingStack {
+            stack: ConcurrentStack::with_capacity(capacity),
+            lock: Mutex::new(()),
+            room: Condvar::new(),
+            waiters: AtomicUsize::new(0),
+        }
+    }
+
+    fn guard(&self) -> MutexGuard<'_, ()> {
+        self.lock.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Push a value on the top of stack, waiting for a pop to make room if it is full.
+    pub fn push(&self, raw: T) {
+        let mut raw = match self.stack.try_push(raw) {
+            Ok(()) => return,
+            Err(raw) => raw,
+        };
+        let mut guard = self.guard();
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        loop {
+            // Checked after announcing the wait, so a pop either makes room here or
+            // sees the waiter and wakes us.
+            raw = match self.stack.try_push(raw) {
+                Ok(()) => break,
+                Err(raw) => raw,
+            };
+            guard = self.room.wait(guard).unwrap_or_else(|e| e.into_inner());
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Push a value on the top of stack, or hand it back if the stack is full.
+    pub fn try_push(&self, raw: T) -> Result<(), T> {
+        self.stack.try_push(raw)
+    }
+
+    /// Pop a value from the top of stack, waking a waiting producer.
+    pub fn pop(&self) -> Option<T> {
+        let v = self.stack.pop();
+        if v.is_some() && self.waiters.load(Ordering::SeqCst) > 0 {
+            let _guard = self.guard();
+            self.room.notify_one();
+        }
+        v
+    }
+
+    /// Most values the stack holds.
+    pub fn capacity(&self) -> usize {
+        self.stack.capacity()
+    }
+
+    /// Number of values on the stack, see `ConcurrentStack::len`.
+    pub fn len(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Check if stack is empty.
+    pub fn is_empty(&self) -> bool {
+        self.stack.is_empty()
+    }
+}
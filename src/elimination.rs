@@ -0,0 +1,62 @@
+//! Elimination array.
+//!
+//! A push that keeps losing the race on `top` parks its node in one of a few
+//! exchange slots for a short while. A pop that keeps losing the race looks into the
+//! slots and takes a parked node directly, so both finish without touching `top`.
+
+use alloc::vec::Vec;
+use sync::{spin_loop, AtomicUsize, Ordering};
+
+use super::{AtomicStampedPtr, Node};
+
+const SLOTS: usize = 8;
+
+// Number of spins a parked push waits for a pop before taking its node back.
+const PATIENCE: usize = 128;
+
+pub struct Elimination<T> {
+    slots: Vec<AtomicStampedPtr<Node<T>>>,
+    next: AtomicUsize,
+}
+
+impl<T> Elimination<T> {
+    pub fn new() -> Self {
+        Elimination {
+            slots: (0..SLOTS).map(|_| AtomicStampedPtr::default()).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    fn slot(&self) -> &AtomicStampedPtr<Node<T>> {
+        &self.slots[self.next.fetch_add(1, Ordering::Relaxed) % SLOTS]
+    }
+
+    /// Offer `node` to a concurrent pop, return true if one took it.
+    pub fn offer(&self, node: *mut Node<T>) -> bool {
+        let slot = self.slot();
+        let (p, v) = slot.load();
+        if !p.is_null() || slot.compare_exchange((p, v), node).is_err() {
+            return false;
+        }
+        let parked = (node, v.wrapping_add(1));
+        for _ in 0..PATIENCE {
+            if slot.load() != parked {
+                return true;
+            }
+            spin_loop();
+        }
+        // Failing to take the node back means a pop got it in the meantime.
+        slot.compare_exchange(parked, core::ptr::null_mut()).is_err()
+    }
+
+    /// Take a node parked by a concurrent push, or null if there is none.
+    pub fn take(&self) -> *mut Node<T> {
+        let slot = self.slot();
+        let (p, v) = slot.load();
+        if !p.is_null() && slot.compare_exchange((p, v), core::ptr::null_mut()).is_ok() {
+            p
+        } else {
+            core::ptr::null_mut()
+        }
+    }
+}
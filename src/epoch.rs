@@ -0,0 +1,135 @@
+//! Epoch based protection.
+//!
+//! Instead of publishing every node it dereferences, a thread pins the stack for the
+//! whole operation: it copies the global epoch into a slot of its own, and clears the
+//! slot when done. Whoever detaches a node advances the global epoch and then waits
+//! for, or checks, the slots still pinned at an epoch up to the one it advanced from.
+//!
+//! All operations on the list heads and slots are SeqCst. A thread pinned at a later
+//! epoch read the global epoch after it was advanced, so after the node was detached,
+//! and cannot reach it from the list any more. Any thread that may still hold the node
+//! is pinned at an earlier epoch and is waited for.
+//!
+//! Pins cost one slot store instead of one per node, but a single long pin holds up
+//! every detach of the stack, not just those of the nodes it reads.
+
+use alloc::boxed::Box;
+use core::ptr;
+use sync::{spin_loop, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+// Slot value of a thread that is not pinned, every epoch is odd.
+const UNPINNED: usize = 0;
+
+/// Epoch slots shared by every thread working on one stack.
+pub struct Epochs {
+    head: AtomicPtr<Slot>,
+    epoch: AtomicUsize,
+}
+
+struct Slot {
+    epoch: AtomicUsize,
+    active: AtomicBool,
+    next: *mut Slot,
+}
+
+/// A pinned slot, unpinned when dropped.
+pub struct Pin<'a> {
+    slot: &'a Slot,
+}
+
+impl Epochs {
+    const_fn! {
+        pub fn new() -> Self {
+            Epochs {
+                head: AtomicPtr::new(ptr::null_mut()),
+                epoch: AtomicUsize::new(UNPINNED + 1),
+            }
+        }
+    }
+
+    /// Pin the current epoch in a free slot, appending a new one if all are in use.
+    pub fn pin(&self) -> Pin<'_> {
+        let slot = self.acquire();
+        slot.epoch.store(self.epoch.load(Ordering::SeqCst), Ordering::SeqCst);
+        Pin { slot }
+    }
+
+    fn acquire(&self) -> &Slot {
+        let mut p = self.head.load(Ordering::Acquire);
+        while !p.is_null() {
+            let slot = unsafe { &*p };
+            if slot.active
+                   .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                   .is_ok() {
+                return slot;
+            }
+            p = slot.next;
+        }
+
+        let slot = Box::into_raw(Box::new(Slot {
+            epoch: AtomicUsize::new(UNPINNED),
+            active: AtomicBool::new(true),
+            next: ptr::null_mut(),
+        }));
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            unsafe {
+                (*slot).next = head;
+            }
+            if self.head
+                   .compare_exchange(head, slot, Ordering::Release, Ordering::Relaxed)
+                   .is_ok() {
+                return unsafe { &*slot };
+            }
+        }
+    }
+
+    /// Advance the epoch and return the one every thread that may still hold a node
+    /// detached before this call is pinned at, or before.
+    pub fn advance(&self) -> usize {
+        // Epochs stay odd, so even when wrapping around they never look unpinned.
+        self.epoch.fetch_add(2, Ordering::SeqCst)
+    }
+
+    /// Check if any thread is pinned at `epoch` or before.
+    ///
+    /// Epochs are compared with wrapping arithmetic, so this stays right as long as no
+    /// thread stays pinned for half the range of `usize` advances.
+    pub fn is_pinned(&self, epoch: usize) -> bool {
+        let mut s = self.head.load(Ordering::Acquire);
+        while !s.is_null() {
+            let slot = unsafe { &*s };
+            let pinned = slot.epoch.load(Ordering::SeqCst);
+            if pinned != UNPINNED && epoch.wrapping_sub(pinned) <= usize::MAX / 2 {
+                return true;
+            }
+            s = slot.next;
+        }
+        false
+    }
+
+    /// Wait until every thread pinned before this call has unpinned.
+    pub fn synchronize(&self) {
+        let epoch = self.advance();
+        while self.is_pinned(epoch) {
+            spin_loop();
+        }
+    }
+}
+
+impl Drop for Epochs {
+    fn drop(&mut self) {
+        let mut p = self.head.load(Ordering::Relaxed);
+        while !p.is_null() {
+            let slot = unsafe { Box::from_raw(p) };
+            p = slot.next;
+        }
+    }
+}
+
+impl<'a> Drop for Pin<'a> {
+    fn drop(&mut self) {
+        self.slot.epoch.store(UNPINNED, Ordering::SeqCst);
+        self.slot.active.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,190 @@
+//! Hazard pointers.
+//!
+//! A thread that wants to dereference a node it does not own publishes the node
+//! address in a hazard slot first, then checks the node is still reachable from the
+//! list it was loaded from. Whoever detaches the node checks the slots before touching
+//! it again:
+//!
+//! * a popper waits for the slots to clear before moving the value out, so a `peek`
+//!   never clones a value being moved;
+//! * a node is only freed while no slot holds it, a protected node is recycled
+//!   through the trash list instead, where it stays valid memory.
+//!
+//! Traversals following `next` deeper than the top cannot validate what they load,
+//! so they register as walkers instead, and no node is freed while any walker runs.
+//! Each walker takes a slot and stores a ticket in it, so a detach waiting for the
+//! walkers only waits for those that started before it, not for a stream of new ones.
+//! To read a value on the way, a traversal protects the node and then checks its
+//! `live` flag, while a popper clears the flag before checking the slots, so either
+//! the value is skipped or the popper waits for the read to finish.
+//!
+//! Either the protecting thread sees the list changed and retries without
+//! dereferencing, or the detaching thread sees the hazard. This rules out
+//! use-after-free, while the stamps on the list heads still catch a protected node
+//! that was recycled and pushed back in the meantime.
+
+use alloc::boxed::Box;
+use core::ptr;
+use sync::{spin_loop, AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+/// Hazard slots shared by every thread working on one stack.
+pub struct Hazards {
+    head: AtomicPtr<Slot>,
+    tickets: AtomicUsize,
+}
+
+// Ticket in the slot of a thread that is not walking, every ticket is odd.
+const NO_WALK: usize = 0;
+
+struct Slot {
+    ptr: AtomicPtr<u8>,
+    walk: AtomicUsize,
+    active: AtomicBool,
+    next: *mut Slot,
+}
+
+/// An acquired slot, released when dropped.
+pub struct Hazard<'a> {
+    slot: &'a Slot,
+}
+
+/// A running traversal, ended when dropped.
+pub struct Walk<'a> {
+    slot: &'a Slot,
+}
+
+impl Hazards {
+    const_fn! {
+        pub fn new() -> Self {
+            Hazards {
+                head: AtomicPtr::new(ptr::null_mut()),
+                tickets: AtomicUsize::new(NO_WALK + 1),
+            }
+        }
+    }
+
+    /// Acquire a free slot, appending a new one if all are in use.
+    pub fn acquire(&self) -> Hazard<'_> {
+        Hazard { slot: self.acquire_slot() }
+    }
+
+    fn acquire_slot(&self) -> &Slot {
+        let mut p = self.head.load(Ordering::Acquire);
+        while !p.is_null() {
+            let slot = unsafe { &*p };
+            if slot.active
+                   .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+                   .is_ok() {
+                return slot;
+            }
+            p = slot.next;
+        }
+
+        let slot = Box::into_raw(Box::new(Slot {
+            ptr: AtomicPtr::new(ptr::null_mut()),
+            walk: AtomicUsize::new(NO_WALK),
+            active: AtomicBool::new(true),
+            next: ptr::null_mut(),
+        }));
+        loop {
+            let head = self.head.load(Ordering::Acquire);
+            unsafe {
+                (*slot).next = head;
+            }
+            if self.head
+                   .compare_exchange(head, slot, Ordering::Release, Ordering::Relaxed)
+                   .is_ok() {
+                return unsafe { &*slot };
+            }
+        }
+    }
+
+    /// Start a traversal, no node may be freed until it ends.
+    pub fn walk(&self) -> Walk<'_> {
+        let slot = self.acquire_slot();
+        // The ticket is stored before the traversal loads anything. A waiter that misses
+        // it checked the slot before the store, so after detaching the nodes it waits
+        // for, which the traversal then cannot reach.
+        slot.walk.store(self.tickets.load(Ordering::SeqCst), Ordering::SeqCst);
+        Walk { slot }
+    }
+
+    /// Wait until every traversal started so far has ended.
+    ///
+    /// Traversals starting from now on get a later ticket and are not waited for.
+    pub fn wait_walkers(&self) {
+        let start = self.tickets.fetch_add(2, Ordering::SeqCst);
+        let mut s = self.head.load(Ordering::Acquire);
+        while !s.is_null() {
+            let slot = unsafe { &*s };
+            loop {
+                let walk = slot.walk.load(Ordering::SeqCst);
+                // Tickets wrap around, the ones up to `start` are those within half the
+                // range below it.
+                if walk == NO_WALK || start.wrapping_sub(walk) > usize::MAX / 2 {
+                    break;
+                }
+                spin_loop();
+            }
+            s = slot.next;
+        }
+    }
+
+    /// Check if a detached node can be freed right now.
+    pub fn may_free<N>(&self, p: *mut N) -> bool {
+        let mut s = self.head.load(Ordering::Acquire);
+        while !s.is_null() {
+            let slot = unsafe { &*s };
+            if slot.walk.load(Ordering::SeqCst) != NO_WALK ||
+               slot.ptr.load(Ordering::SeqCst) == p as *mut u8 {
+                return false;
+            }
+            s = slot.next;
+        }
+        true
+    }
+
+    /// Check if any thread currently protects `p`.
+    pub fn is_protected<N>(&self, p: *mut N) -> bool {
+        let mut s = self.head.load(Ordering::Acquire);
+        while !s.is_null() {
+            let slot = unsafe { &*s };
+            if slot.ptr.load(Ordering::SeqCst) == p as *mut u8 {
+                return true;
+            }
+            s = slot.next;
+        }
+        false
+    }
+}
+
+impl Drop for Hazards {
+    fn drop(&mut self) {
+        let mut p = self.head.load(Ordering::Relaxed);
+        while !p.is_null() {
+            let slot = unsafe { Box::from_raw(p) };
+            p = slot.next;
+        }
+    }
+}
+
+impl<'a> Hazard<'a> {
+    /// Publish `p` as being in use by this thread.
+    pub fn protect<N>(&self, p: *mut N) {
+        self.slot.ptr.store(p as *mut u8, Ordering::SeqCst);
+    }
+}
+
+impl<'a> Drop for Hazard<'a> {
+    fn drop(&mut self) {
+        self.slot.ptr.store(ptr::null_mut(), Ordering::Release);
+        self.slot.active.store(false, Ordering::Release);
+    }
+}
+
+impl<'a> Drop for Walk<'a> {
+    fn drop(&mut self) {
+        self.slot.walk.store(NO_WALK, Ordering::SeqCst);
+        self.slot.active.store(false, Ordering::Release);
+    }
+}
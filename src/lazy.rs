@@ -0,0 +1,84 @@
+//! `atomic_stamped_ptr::AtomicStampedPtr` with its cell allocated on first write.
+//!
+//! The original allocates the 16 byte cell its `cmpxchg16b` works on when created,
+//! which keeps a stack holding one from being built by a `const fn`. This wrapper
+//! starts with no cell and reads as null with stamp 0 until the first `swap` or
+//! `compare_exchange` allocates it, exactly the value a fresh cell holds, so callers
+//! cannot tell the difference.
+//!
+//! Threads racing to allocate the cell agree on it with a CAS, the loser frees its own.
+
+use alloc::boxed::Box;
+use atomic_stamped_ptr::AtomicStampedPtr as Cell;
+use core::ptr;
+use sync::{AtomicPtr, Ordering};
+
+pub struct AtomicStampedPtr<T> {
+    cell: AtomicPtr<Cell<T>>,
+}
+
+pub type Stamp = usize;
+
+unsafe impl<T> Send for AtomicStampedPtr<T> {}
+unsafe impl<T> Sync for AtomicStampedPtr<T> {}
+
+impl<T> Default for AtomicStampedPtr<T> {
+    fn default() -> Self {
+        AtomicStampedPtr::null()
+    }
+}
+
+impl<T> AtomicStampedPtr<T> {
+    pub const fn null() -> Self {
+        AtomicStampedPtr { cell: AtomicPtr::new(ptr::null_mut()) }
+    }
+
+    // SeqCst so that the cell is published as strongly as the `lock cmpxchg16b` on it
+    // orders everything else, the hazard pointers depend on it.
+    fn cell(&self) -> &Cell<T> {
+        let p = self.cell.load(Ordering::SeqCst);
+        if !p.is_null() {
+            return unsafe { &*p };
+        }
+        let new = Box::into_raw(Box::new(Cell::default()));
+        match self.cell.compare_exchange(ptr::null_mut(), new, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => unsafe { &*new },
+            Err(p) => {
+                drop(unsafe { Box::from_raw(new) });
+                unsafe { &*p }
+            }
+        }
+    }
+
+    /// Load the pointer and its stamp.
+    pub fn load(&self) -> (*mut T, Stamp) {
+        let p = self.cell.load(Ordering::SeqCst);
+        if p.is_null() {
+            (ptr::null_mut(), 0)
+        } else {
+            unsafe { (*p).load() }
+        }
+    }
+
+    /// Store `ptr`, bump the stamp and return the old pointer.
+    pub fn swap(&self, ptr: *mut T) -> *mut T {
+        self.cell().swap(ptr)
+    }
+
+    /// Store `ptr` and bump the stamp if the current value is `current`.
+    pub fn compare_exchange(&self,
+                            current: (*mut T, Stamp),
+                            ptr: *mut T)
+                            -> Result<(*mut T, Stamp), (*mut T, Stamp)> {
+        self.cell().compare_exchange(current, ptr)
+    }
+}
+
+impl<T> Drop for AtomicStampedPtr<T> {
+    fn drop(&mut self) {
+        let p = *self.cell.get_mut();
+        if !p.is_null() {
+            drop(unsafe { Box::from_raw(p) });
+        }
+    }
+}
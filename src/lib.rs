@@ -1,6 +1,16 @@
 //! Lock free stack for x86_64.
 //! Providing basic thread safe stack operations.
 //!
+//! # Targets
+//!
+//! | target         | stamped pointer                       | lock free |
+//! |----------------|---------------------------------------|-----------|
+//! | x86_64, `std`  | `atomic-stamped-ptr` (`cmpxchg16b`)   | yes       |
+//! | anything else  | built-in spinlock-guarded fallback    | no        |
+//!
+//! Every target gets a working stack with the same API, `IS_LOCK_FREE` tells which
+//! kind was built.
+//!
 //! # Examples
 //!
 //! ```
@@ -8,7 +18,6 @@
 //! use std::sync::Arc;
 //! use std::thread;
 //!
-//!
 //! let stack = Arc::new(ConcurrentStack::new());
 //! let pusher = stack.clone();
 //! let producer = thread::spawn(move || {
@@ -18,194 +27,5625 @@
 //! });
 //! let poper = stack.clone();
 //! let consumer = thread::spawn(move || {
+//!     let mut popped = 0;
 //!     for _ in 0..100 {
 //!         if let Some(v) = poper.pop() {
 //!             // Deal with v.
+//!             assert!(v < 100);
+//!             popped += 1;
 //!         }
 //!     }
+//!     popped
 //! });
-//! producer.join();
-//! consumer.join();
+//! producer.join().unwrap();
+//! let popped = consumer.join().unwrap();
+//! // Whatever the consumer missed is still on the stack.
+//! assert_eq!(popped + stack.len(), 100);
+//! ```
+//!
+//! # Trait objects
+//!
+//! Values of different types sharing a trait can be stored boxed. `push` takes the
+//! box, which coerces to the trait object on its own, so no helper is needed. A stack
+//! of `Send` closures makes a simple lock free task pool, run in no particular order:
+//!
+//! ```
+//! use concurrent_stack::ConcurrentStack;
+//! use std::sync::atomic::{AtomicUsize, Ordering};
+//! use std::sync::Arc;
+//! use std::thread;
+//!
+//! let tasks: Arc<ConcurrentStack<Box<dyn FnOnce() + Send>>> = Arc::new(ConcurrentStack::new());
+//! let done = Arc::new(AtomicUsize::new(0));
+//! for i in 0..8 {
+//!     let done = done.clone();
+//!     tasks.push(Box::new(move || {
+//!         done.fetch_add(i, Ordering::Relaxed);
+//!     }));
+//! }
+//! let mut workers = Vec::new();
+//! for _ in 0..2 {
+//!     let tasks = tasks.clone();
+//!     workers.push(thread::spawn(move || {
+//!         while let Some(task) = tasks.pop() {
+//!             task();
+//!         }
+//!     }));
+//! }
+//! for w in workers {
+//!     w.join().unwrap();
+//! }
+//! assert_eq!(done.load(Ordering::Relaxed), 28);
+//! ```
+//!
+//! The trait object must be `Send` for the stack to be shared between threads. It
+//! may also borrow, like `Box<dyn FnMut() + '_>`, as long as the stack does not
+//! outlive what it borrows.
+//!
+//! # ABA and the stamp
+//!
+//! Both list heads pair the pointer with a stamp that every successful CAS bumps, so a
+//! thread that loaded `(p, n)` cannot swap in a stale `next` after `p` was popped,
+//! recycled and pushed back. The stamp is 64 bits wide: a `usize` next to the pointer
+//! on x86_64 and a `u64` in the fallback. It wraps to 0 after 2^64 (about 1.8e19)
+//! updates of one head, so the check can only be fooled if a thread is stalled
+//! between its load and its CAS while exactly a multiple of 2^64 updates happen.
+//! At a billion updates per second that takes about 584 years, which the crate
+//! treats as impossible. The stamp does not depend on how often one node is reused:
+//! it counts updates of the head, so cycling a few nodes through the trash as fast as
+//! possible gets no closer to a wrap than pushing fresh ones.
+//!
+//! # Linearization points
+//!
+//! Push and pop are linearizable: each takes effect at a single instant between its
+//! call and its return, and the stack behaves as if the operations had run one by one
+//! in the order of those instants.
+//!
+//! * `push` takes effect at the successful CAS of `top` that links its node.
+//! * `pop` takes effect at the successful CAS of `top` that detaches the node it
+//!   returns, and a pop returning `None` at the load of `top` that found it null.
+//! * With `with_elimination`, a push and a pop that meet in the exchange array take
+//!   effect together when the pop takes the node, the push right before the pop.
+//! * `push_iter` and `append` push all their values at the one CAS linking the chain,
+//!   `pop_all`, `clear`, `take_chain` and `pop_n` pop theirs at the one swap or CAS
+//!   detaching it.
+//! * `is_empty` takes effect at its load of `top`, and `peek` at the load of `top` it
+//!   validated its hazard pointer against.
+//!
+//! The CAS and loads of `top` are SeqCst operations on both stamped pointers, a
+//! `lock cmpxchg16b` on x86_64 and behind a SeqCst spinlock in the fallback, so these
+//! instants are totally ordered and agree with real time. `len`, `iter_snapshot` and
+//! the other traversals are not linearizable with the operations above. For
+//! zero-sized values everything happens on the `len` counter instead, and push and pop
+//! take effect at their read-modify-write of it, which are totally ordered as well.
+//!
+//! # Reclamation
+//!
+//! A popped node may still be read by another thread that loaded it just before, so
+//! its value is only moved out, and the node only freed, once no such reader is left.
+//! The second type parameter of `ConcurrentStack` picks how that is tracked:
+//!
+//! * `HazardReclaim`, the default, uses hazard pointers: readers publish each node
+//!   they dereference, and a pop only waits for the readers of its own node.
+//! * `EpochReclaim` pins the stack once per operation instead, which makes
+//!   traversals like `iter_snapshot` cheaper, but every pop then waits for all the
+//!   operations that were running when it detached its node, so pops block on
+//!   concurrent readers. `pop_n` and the other batch operations wait once per batch.
+//!
+//! Both rule out use-after-free, and both keep the stamps against ABA, since nodes are
+//! recycled either way. Only `with_reclaim` creates a stack with another backend, the
+//! other constructors use the default one.
+//!
+//! With the `crossbeam-epoch` feature, `CrossbeamReclaim` leaves reclamation to the
+//! global collector of that crate: popped nodes are handed to it instead of recycled,
+//! so no node is reused while any thread could still hold it, and pops never wait.
+//! It gives up reading values in place for that, so the methods doing so, like `peek`
+//! and `iter_snapshot`, require a backend implementing `SharedRead`.
+//!
+//! # `no_std`
+//!
+//! The `std` feature is enabled by default. Without it the crate only needs `core` and
+//! `alloc`, and push, pop, peek, is_empty and the other non-blocking methods stay
+//! available. Contended operations then keep spinning instead of yielding the thread,
+//! and `BlockingStack`, `BoundedBlockingStack`, `AsyncStack`, as well as the
+//! `with_local_cache` and `with_background_reclaim` constructors, are left out.
+//!
+//! The `atomic-stamped-ptr` dependency links `std` itself, so without the feature it
+//! is left out too, and x86_64 uses the spinlock fallback like the other targets: the
+//! stack is then not lock free anywhere. A bare metal build checks that nothing else
+//! pulls in `std`:
+//!
+//! ```text
+//! cargo build --no-default-features --target thumbv7em-none-eabihf
+//! ```
+//!
+//! # Stats
+//!
+//! With the `stats` feature, `ConcurrentStack::stats` returns how many values were
+//! pushed and popped, how often a CAS had to be retried, how many values went through
+//! the elimination array, and the current length and trash size. Without it the
+//! counters are not compiled in at all.
+//!
+//! To feed the same events, and node allocations and recycling, into a tracing or
+//! metrics system instead, give the stack an `Observer` with `with_observer`. That
+//! works with or without the feature.
+//!
+//! # Debugging with sanitizers
+//!
+//! Recycled nodes stay allocated, which hides use-after-free bugs from Miri and
+//! AddressSanitizer. The `no-recycle` feature frees every popped node right away and
+//! allocates a fresh one for every push, `with_preallocated` and `reserve` then do
+//! nothing. This is much slower and only meant for hunting memory bugs:
+//!
+//! ```text
+//! cargo +nightly miri test --features no-recycle
+//! ```
+//!
+//! # Model checking
+//!
+//! The CAS loops, the hazard pointers and the node recycling can be checked under
+//! every interleaving with [loom](https://docs.rs/loom). Built with `--cfg loom` the
+//! stack uses loom's atomics and the portable stamped pointer, and only the loom tests
+//! are compiled:
+//!
+//! ```text
+//! RUSTFLAGS="--cfg loom" LOOM_MAX_PREEMPTIONS=3 cargo test --release --lib
+//! ```
+//!
+//! # Fuzzing
+//!
+//! The `push_pop` target under `fuzz/` runs schedules of pushes, pops and peeks read
+//! from the input on up to four threads, and checks that no value is lost or popped
+//! twice. Single threaded schedules are also checked against a `Vec`. It needs
+//! [cargo-fuzz](https://github.com/rust-fuzz/cargo-fuzz) and a nightly compiler:
+//!
+//! ```text
+//! cargo +nightly fuzz run push_pop
 //! ```
+//!
+//! # Serde
+//!
+//! With the `serde` feature, a stack serializes to a sequence from top to bottom and
+//! deserializes back in the same order. Serializing clones the values like
+//! `iter_snapshot`, so it needs `T: Clone + Sync` and a `SharedRead` backend.
+//!
+//! # Custom allocators
+//!
+//! On a nightly compiler, the `allocator_api` feature adds a third type parameter to
+//! the stack: `ConcurrentStack<T, R, A>` allocates its nodes with `A`, any `Allocator`,
+//! the global one by default. Create such a stack with `new_in` or `with_reclaim_in`.
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+#![cfg_attr(feature = "allocator_api", feature(allocator_api))]
+
+extern crate alloc;
+#[cfg(any(feature = "std", test))]
+extern crate core;
+#[cfg(all(target_arch = "x86_64", feature = "std", not(loom)))]
 extern crate atomic_stamped_ptr;
+#[cfg(feature = "crossbeam-epoch")]
+extern crate crossbeam_epoch;
+#[cfg(loom)]
+extern crate loom;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+
+// Declares a `const fn`, or a plain one under loom, whose atomics cannot be created in
+// a constant.
+macro_rules! const_fn {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])* $vis const fn $($rest)*
+        #[cfg(loom)]
+        $(#[$attr])* $vis fn $($rest)*
+    };
+}
+
+#[cfg(feature = "std")]
+mod async_stack;
+#[cfg(feature = "std")]
+mod background;
+mod backoff;
+#[cfg(feature = "std")]
+mod blocking;
+mod elimination;
+mod epoch;
+mod hazard;
+#[cfg(all(target_arch = "x86_64", feature = "std", not(loom)))]
+mod lazy;
+#[cfg(feature = "std")]
+mod local;
+mod node_alloc;
+mod padded;
+mod policy;
+mod reclaim;
+#[cfg(feature = "serde")]
+mod serde_impls;
+#[cfg(any(not(target_arch = "x86_64"), not(feature = "std"), test, loom))]
+mod stamped;
+mod stats;
+mod sync;
+
+use alloc::sync::Arc;
+#[cfg(feature = "std")]
+use background::Worker;
+use alloc::vec::Vec;
+use backoff::Backoff;
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use elimination::Elimination;
+#[cfg(all(target_arch = "x86_64", feature = "std", not(loom)))]
+use lazy::{AtomicStampedPtr, Stamp};
+#[cfg(feature = "allocator_api")]
+use node_alloc::Allocator;
+use node_alloc::{Global, NodeAlloc};
+use padded::CachePadded;
+use reclaim::Guard;
+#[cfg(any(not(target_arch = "x86_64"), not(feature = "std"), loom))]
+use stamped::{AtomicStampedPtr, Stamp};
+use stats::Stats;
+#[cfg(feature = "std")]
+use sync::spin_loop;
+use sync::{AtomicBool, AtomicPtr, AtomicUsize, Ordering, UnsafeCell};
+
+#[cfg(feature = "std")]
+pub use async_stack::{AsyncStack, PopFuture};
+#[cfg(feature = "std")]
+pub use blocking::{BlockingStack, BoundedBlockingStack};
+#[cfg(feature = "std")]
+pub use policy::{ParkPolicy, YieldPolicy};
+pub use policy::{RecyclePolicy, RetryPolicy, SpinPolicy};
+#[cfg(feature = "crossbeam-epoch")]
+pub use reclaim::CrossbeamReclaim;
+pub use reclaim::{EpochReclaim, HazardReclaim, Reclaim, SharedRead};
+#[cfg(feature = "stats")]
+pub use stats::StackStats;
+pub use stats::Observer;
 
-use atomic_stamped_ptr::AtomicStampedPtr;
+/// Whether stack operations are lock free on this target.
+///
+/// Only x86_64 has the double-width CAS `atomic-stamped-ptr` needs, and only with the
+/// `std` feature is it used. Otherwise a spinlock guards the stamped pointers, which
+/// works the same but may block.
+pub const IS_LOCK_FREE: bool = cfg!(all(target_arch = "x86_64", feature = "std"));
 
 /// A lock free FILO structure.
-pub struct ConcurrentStack<T> {
-    top: AtomicStampedPtr<Node<T>>,
-    trash: AtomicStampedPtr<Node<T>>,
+///
+/// The fields written by push/pop and those written by node recycling each sit on
+/// their own 64 byte cache line, which takes 256 bytes per stack. The heads of the
+/// lists point to cells allocated by `atomic-stamped-ptr` on first use, which may still
+/// share a line.
+///
+/// Values of a zero-sized type like `()` are only counted, no node is allocated for them.
+///
+/// Forgetting a stack, with `mem::forget` or a reference cycle, leaks its nodes and the
+/// values on it, but is safe: no other stack or thread keeps pointers into them once
+/// every operation on the stack has returned.
+pub struct ConcurrentStack<T, R: Reclaim = HazardReclaim, A: NodeAlloc = Global> {
+    top: CachePadded<AtomicStampedPtr<Node<T>>>,
+    trash: CachePadded<AtomicStampedPtr<Node<T>>>,
+    len: CachePadded<AtomicUsize>,
+    capacity: usize,
+    closed: AtomicBool,
+    trash_len: CachePadded<AtomicUsize>,
+    trash_limit: usize,
+    // With `with_fifo_trash`, nodes put in the trash since it was last refilled, newest
+    // first. Its cell is only written in that mode, so it needs no line of its own.
+    fifo_trash: bool,
+    recent: AtomicStampedPtr<Node<T>>,
+    // Only ever set with the global allocator, which the cache frees its nodes with.
+    local_cache: bool,
+    elimination: Option<Elimination<T>>,
+    policy: Option<Arc<dyn RetryPolicy>>,
+    recycle: Option<Arc<dyn RecyclePolicy>>,
+    reclaim: R,
+    alloc: A,
+    stats: Stats,
+    // Dropped last, once the nodes are freed.
+    #[cfg(feature = "std")]
+    worker: Option<Worker>,
 }
 
-struct Node<T> {
-    data: Option<T>,
-    next: *mut Node<T>,
+// Values are moved in by one thread and out by another, but never shared between
+// threads through the stack, so `T: Send` is enough for both impls. Methods reading a
+// value in place from several threads at once, like `peek`, require `T: Sync` on
+// their own.
+unsafe impl<T: Send, R: Reclaim, A: NodeAlloc> Send for ConcurrentStack<T, R, A> {}
+unsafe impl<T: Send, R: Reclaim, A: NodeAlloc> Sync for ConcurrentStack<T, R, A> {}
+
+/// Default number of popped nodes kept for reuse.
+pub const DEFAULT_TRASH_LIMIT: usize = 1024;
+
+/// Most nodes a thread keeps in its cache for `ConcurrentStack::with_local_cache`.
+#[cfg(feature = "std")]
+pub const LOCAL_CACHE_SIZE: usize = local::CAPACITY;
+
+/// Number of failed attempts after which `pop_yield` starts yielding the thread.
+#[cfg(feature = "std")]
+pub const YIELD_AFTER: u32 = 4;
+
+// Whether popped nodes are reused, turned off by the `no-recycle` feature.
+const RECYCLE: bool = !cfg!(feature = "no-recycle");
+
+/// A node of the stack, holding one value.
+///
+/// Opaque, only its address comes out, see `ConcurrentStack::current_top`.
+// `repr(C)` so that the layout `node_layout` reports only depends on `T`, not on how
+// a compiler version orders the fields.
+#[repr(C)]
+pub struct Node<T> {
+    // Written by the thread owning the node, read in place by traversals and `peek`.
+    data: UnsafeCell<Option<T>>,
+    // Atomic because traversals may read it while the owner of the node relinks it.
+    // Stored with Release and loaded with Acquire, see `push_top`.
+    next: AtomicPtr<Node<T>>,
+    // Set while traversals may read `data`, cleared before the value is moved out.
+    live: AtomicBool,
 }
 
 impl<T> ConcurrentStack<T> {
-    pub fn new() -> Self {
-        ConcurrentStack {
-            top: AtomicStampedPtr::default(),
-            trash: AtomicStampedPtr::default(),
+    const_fn! {
+        /// Create an empty stack.
+        ///
+        /// This is a `const fn`, so a stack can live in a `static`. Nothing is allocated
+        /// before the stack is used, on x86_64 the cells of the `atomic-stamped-ptr` list
+        /// heads are allocated on their first write.
+        ///
+        /// ```
+        /// use concurrent_stack::ConcurrentStack;
+        ///
+        /// static STACK: ConcurrentStack<u64> = ConcurrentStack::new();
+        ///
+        /// STACK.push(1);
+        /// assert_eq!(STACK.pop(), Some(1));
+        /// ```
+        pub fn new() -> Self {
+            Self::with_reclaim()
+        }
+    }
+
+    /// Create a stack holding at most `max` values when filled with `try_push`.
+    ///
+    /// `push` never fails and is not limited by the capacity.
+    pub fn with_capacity(max: usize) -> Self {
+        let mut stack = Self::new();
+        stack.capacity = max;
+        stack
+    }
+
+    /// Create a stack keeping at most `n` popped nodes for reuse instead of
+    /// `DEFAULT_TRASH_LIMIT`.
+    ///
+    /// Recycled nodes save an allocation on the next push but stay allocated until the
+    /// stack is dropped. A larger limit favours throughput after bursts, a smaller one
+    /// gives memory back sooner.
+    pub fn with_trash_limit(n: usize) -> Self {
+        let mut stack = Self::new();
+        stack.trash_limit = n;
+        stack
+    }
+
+    /// Create a stack with `n` empty nodes ready for reuse, so that the first `n`
+    /// pushes don't allocate.
+    ///
+    /// The memory of all `n` nodes is taken upfront and kept for reuse, even if the trash
+    /// limit is lower, so that steady state stays allocation free as long as the stack
+    /// holds at most `n` values.
+    pub fn with_preallocated(n: usize) -> Self {
+        let mut stack = Self::new();
+        stack.trash_limit = stack.trash_limit.max(n);
+        stack.fill_trash(n);
+        stack
+    }
+
+    /// Create a stack holding the values of `v`, `v[0]` at the bottom and the last value
+    /// on the top.
+    pub fn from_vec(v: Vec<T>) -> Self {
+        let stack = Self::new();
+        stack.push_iter(v);
+        stack
+    }
+
+    /// Create a stack reporting pushes, pops, CAS retries and node allocation and
+    /// recycling to `observer`, for tracing or metrics.
+    ///
+    /// Works with or without the `stats` feature. A stack without an observer only
+    /// checks that it has none at each of these points.
+    ///
+    /// ```
+    /// use concurrent_stack::{ConcurrentStack, Observer};
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    ///
+    /// struct Allocs(Arc<AtomicUsize>);
+    ///
+    /// impl Observer for Allocs {
+    ///     fn on_node_alloc(&self) {
+    ///         self.0.fetch_add(1, Ordering::Relaxed);
+    ///     }
+    /// }
+    ///
+    /// let allocs = Arc::new(AtomicUsize::new(0));
+    /// let stack = ConcurrentStack::with_observer(Allocs(allocs.clone()));
+    /// stack.push(1);
+    /// stack.pop();
+    /// stack.push(2);
+    /// // The second push got the node of the first one back.
+    /// # #[cfg(not(feature = "no-recycle"))]
+    /// assert_eq!(allocs.load(Ordering::Relaxed), 1);
+    /// ```
+    pub fn with_observer<O: Observer + 'static>(observer: O) -> Self {
+        let mut stack = Self::new();
+        stack.stats.observer = Some(Arc::new(observer));
+        stack
+    }
+
+    /// Create a stack reusing popped nodes oldest first instead of newest first.
+    ///
+    /// Popped nodes are kept for reuse as usual, up to the trash limit, but a push takes
+    /// the node that has waited longest. For a fixed single threaded sequence of pushes
+    /// and pops, the addresses handed out and the order of allocations and frees are
+    /// then reproducible, and every recycled node cools down before it is reused. Under
+    /// concurrency the order is only roughly first in first out. This costs a little
+    /// more per recycled node and is meant for debugging allocator interactions.
+    pub fn with_fifo_trash() -> Self {
+        let mut stack = Self::new();
+        stack.fifo_trash = true;
+        stack
+    }
+
+    /// Create a shared stack whose trash is trimmed by a thread of its own while the
+    /// stack is idle.
+    ///
+    /// Every `interval` the thread checks if any value was pushed or popped since it last
+    /// looked, and if none was, frees nodes kept for reuse down to `low_water` with
+    /// `shrink_to`. Busy periods keep all their nodes, and the memory they needed is
+    /// given back once they are over. The thread only holds a weak reference, it is
+    /// stopped and joined when the stack is dropped. Clones of the stack get no thread.
+    #[cfg(feature = "std")]
+    pub fn with_background_reclaim(interval: std::time::Duration, low_water: usize) -> Arc<Self>
+    where
+        T: Send + 'static,
+    {
+        Arc::new_cyclic(|weak: &alloc::sync::Weak<Self>| {
+            let weak = weak.clone();
+            let mut seen = None;
+            let mut stack = Self::new();
+            stack.worker = Some(Worker::spawn(interval, move || {
+                // Not there yet while this constructor runs, or gone while it drops.
+                if let Some(stack) = weak.upgrade() {
+                    let stamp = stack.top.load().1;
+                    if seen == Some(stamp) {
+                        stack.shrink_to(low_water);
+                    }
+                    seen = Some(stamp);
+                }
+            }));
+            stack
+        })
+    }
+
+    /// Create a stack recycling popped nodes through a small cache of each thread
+    /// before the shared trash.
+    ///
+    /// A pop puts its node in the cache of its thread, and a push takes one from there,
+    /// without any CAS on the trash list. Once the cache is full, nodes go to the trash
+    /// as usual, and an empty cache is refilled with a few nodes from the trash at once.
+    /// Each thread caches at most `LOCAL_CACHE_SIZE` nodes, over all stacks, and frees
+    /// them when it exits. Cached nodes are not counted by `trash_len` and `shrink`
+    /// cannot reach them.
+    #[cfg(feature = "std")]
+    pub fn with_local_cache() -> Self {
+        let mut stack = Self::new();
+        stack.local_cache = true;
+        stack
+    }
+
+    /// Create a stack pairing up contended pushes and pops.
+    ///
+    /// A push failing its CAS on the top parks its value in a small exchange array for a
+    /// moment, where a pop failing its own CAS can take it without touching the top.
+    /// This helps when many threads push and pop at the same rate, and only costs a
+    /// little latency otherwise.
+    pub fn with_elimination() -> Self {
+        let mut stack = Self::new();
+        stack.elimination = Some(Elimination::new());
+        stack
+    }
+
+    /// Create a stack waiting between CAS retries as `policy` says, instead of spinning
+    /// for a growing number of iterations and then yielding the thread.
+    ///
+    /// ```
+    /// use concurrent_stack::{ConcurrentStack, SpinPolicy};
+    ///
+    /// // Never yield, and spin at most 2^4 iterations between retries.
+    /// let stack = ConcurrentStack::with_policy(SpinPolicy::new(4));
+    /// stack.push(1);
+    /// assert_eq!(stack.pop(), Some(1));
+    /// ```
+    pub fn with_policy<P: RetryPolicy + 'static>(policy: P) -> Self {
+        let mut stack = Self::new();
+        stack.policy = Some(Arc::new(policy));
+        stack
+    }
+
+    /// Create a stack asking `policy` whether to keep each popped node for reuse or to
+    /// free it, instead of keeping up to the trash limit.
+    ///
+    /// A node another thread may still read is kept regardless, and with the
+    /// `no-recycle` feature the policy is never asked.
+    ///
+    /// ```
+    /// use concurrent_stack::{ConcurrentStack, RecyclePolicy};
+    ///
+    /// // Recycle popped nodes until 16 of them wait for reuse, free any further ones.
+    /// struct Watermark;
+    ///
+    /// impl RecyclePolicy for Watermark {
+    ///     fn should_recycle(&self, trash_len: usize) -> bool {
+    ///         trash_len < 16
+    ///     }
+    /// }
+    ///
+    /// let stack = ConcurrentStack::with_recycle_policy(Watermark);
+    /// stack.push(1);
+    /// assert_eq!(stack.pop(), Some(1));
+    /// ```
+    pub fn with_recycle_policy<P: RecyclePolicy + 'static>(policy: P) -> Self {
+        let mut stack = Self::new();
+        stack.recycle = Some(Arc::new(policy));
+        stack
+    }
+
+}
+
+impl<T, R: Reclaim> ConcurrentStack<T, R> {
+    const_fn! {
+        /// Create an empty stack protecting its nodes with the `R` backend, a `const fn`
+        /// like `new`.
+        ///
+        /// ```
+        /// use concurrent_stack::{ConcurrentStack, EpochReclaim};
+        ///
+        /// let stack = ConcurrentStack::<i32, EpochReclaim>::with_reclaim();
+        /// stack.push(1);
+        /// assert_eq!(stack.pop(), Some(1));
+        /// ```
+        pub fn with_reclaim() -> Self {
+            Self::with_parts(Global)
+        }
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<T, A: Allocator + Send + Sync> ConcurrentStack<T, HazardReclaim, A> {
+    /// Create an empty stack allocating its nodes with `alloc`.
+    ///
+    /// Every node the stack allocates, recycles and frees goes through `alloc`, only the
+    /// hazard slots of the threads using the stack come from the global allocator.
+    /// `with_local_cache` is not available with another allocator.
+    pub fn new_in(alloc: A) -> Self {
+        Self::with_parts(alloc)
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<T, R: Reclaim, A: Allocator + Send + Sync> ConcurrentStack<T, R, A> {
+    /// Create an empty stack protecting its nodes with the `R` backend and allocating
+    /// them with `alloc`, see `new_in`.
+    pub fn with_reclaim_in(alloc: A) -> Self {
+        Self::with_parts(alloc)
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAlloc> ConcurrentStack<T, R, A> {
+    const_fn! {
+        // What every constructor ends in.
+        fn with_parts(alloc: A) -> Self {
+            ConcurrentStack {
+                top: CachePadded::new(AtomicStampedPtr::null()),
+                trash: CachePadded::new(AtomicStampedPtr::null()),
+                len: CachePadded::new(AtomicUsize::new(0)),
+                capacity: usize::MAX,
+                closed: AtomicBool::new(false),
+                trash_len: CachePadded::new(AtomicUsize::new(0)),
+                trash_limit: DEFAULT_TRASH_LIMIT,
+                fifo_trash: false,
+                recent: AtomicStampedPtr::null(),
+                local_cache: false,
+                elimination: None,
+                policy: None,
+                recycle: None,
+                #[cfg(not(loom))]
+                reclaim: R::NEW,
+                #[cfg(loom)]
+                reclaim: R::new(),
+                alloc,
+                stats: Stats::new(),
+                #[cfg(feature = "std")]
+                worker: None,
+            }
+        }
+    }
+
+    // A new empty stack with the same settings, of values of any type.
+    fn empty_like<U>(&self) -> ConcurrentStack<U, R, A>
+    where
+        A: Clone,
+    {
+        let mut stack = ConcurrentStack::with_parts(self.alloc.clone());
+        stack.capacity = self.capacity;
+        stack.trash_limit = self.trash_limit;
+        stack.fifo_trash = self.fifo_trash;
+        stack.local_cache = self.local_cache;
+        if self.elimination.is_some() {
+            stack.elimination = Some(Elimination::new());
+        }
+        stack.policy = self.policy.clone();
+        stack.recycle = self.recycle.clone();
+        stack.stats.observer = self.stats.observer.clone();
+        stack
+    }
+
+    /// Move the values into a `Vec` in pop order, the top first.
+    ///
+    /// This is the reverse of `from_vec`, so `ConcurrentStack::from_vec(v).into_vec()`
+    /// is `v` reversed.
+    pub fn into_vec(self) -> Vec<T> {
+        self.pop_all()
+    }
+
+    /// Fold the values into one in pop order, the top first, consuming the stack.
+    ///
+    /// The same as `into_iter().fold(init, f)`. If `f` panics, the values not folded
+    /// yet are dropped with the stack.
+    pub fn fold<B, F: FnMut(B, T) -> B>(self, init: B, mut f: F) -> B {
+        let mut acc = init;
+        while let Some(value) = self.pop() {
+            acc = f(acc, value);
+        }
+        acc
+    }
+
+    /// Turn every value into another with `f`, keeping their order, and return them in a
+    /// new stack with the same settings.
+    ///
+    /// `f` is called from the top down. Owning the stack, this walks the list without any
+    /// CAS, and when a node of `U` has the same size and alignment as one of `T` the
+    /// nodes are reused in place rather than allocated again. If `f` panics, the values
+    /// not mapped yet are leaked.
+    pub fn map<U, F: FnMut(T) -> U>(self, mut f: F) -> ConcurrentStack<U, R, A>
+    where
+        A: Clone,
+    {
+        let mapped = self.empty_like();
+        if Self::is_zst() || ConcurrentStack::<U, R, A>::is_zst() ||
+           Layout::new::<Node<T>>() != Layout::new::<Node<U>>() {
+            let values = self.into_vec().into_iter().map(f).collect::<Vec<_>>();
+            mapped.push_iter(values.into_iter().rev());
+            return mapped;
+        }
+        let first = self.top.swap(core::ptr::null_mut());
+        let mut p = first;
+        let mut n = 0;
+        while !p.is_null() {
+            let Node { data, next, .. } = unsafe { core::ptr::read(p) };
+            let next = next.load(Ordering::Relaxed);
+            let value = data.into_inner().map(&mut f);
+            unsafe {
+                core::ptr::write(p as *mut Node<U>,
+                                 Node {
+                                     next: AtomicPtr::new(next as *mut Node<U>),
+                                     live: AtomicBool::new(value.is_some()),
+                                     data: UnsafeCell::new(value),
+                                 });
+            }
+            p = next;
+            n += 1;
+        }
+        mapped.top.swap(first as *mut Node<U>);
+        mapped.len.store(n, Ordering::Release);
+        mapped.stats.pushed(n);
+        mapped
+    }
+
+    fn backoff(&self) -> Backoff<'_> {
+        Backoff::new(self.policy.as_deref())
+    }
+
+    // A pop may decrement the counter before the matching push has incremented it,
+    // which wraps it below zero for a short moment.
+    fn clamp_len(n: usize) -> usize {
+        if n > isize::MAX as usize {
+            0
+        } else {
+            n
+        }
+    }
+
+    // Values of a zero-sized type carry no data, so for them the stack is only the
+    // `len` counter and no node is ever allocated. Any value of such a type is as good
+    // as another, the pushed ones are forgotten and popped ones conjured in their place.
+    fn is_zst() -> bool {
+        core::mem::size_of::<T>() == 0
+    }
+
+    // Conjure a value of a zero-sized type, one that was pushed and forgotten.
+    unsafe fn zst_value() -> T {
+        core::ptr::NonNull::<T>::dangling().as_ptr().read()
+    }
+
+    // Take up to `max` zero-sized values off the counter and return how many were taken.
+    fn take_zst(&self, max: usize) -> usize {
+        let mut n = self.len.load(Ordering::Acquire);
+        loop {
+            let taken = Self::clamp_len(n).min(max);
+            if taken == 0 {
+                return 0;
+            }
+            match self.len.compare_exchange_weak(n,
+                                                 n - taken,
+                                                 Ordering::AcqRel,
+                                                 Ordering::Acquire) {
+                Ok(_) => return taken,
+                Err(current) => n = current,
+            }
+        }
+    }
+
+    // Count one more value unless the stack is full.
+    fn reserve_len(&self) -> bool {
+        let mut n = self.len.load(Ordering::Acquire);
+        loop {
+            if Self::clamp_len(n) >= self.capacity {
+                return false;
+            }
+            match self.len.compare_exchange_weak(n,
+                                                 n.wrapping_add(1),
+                                                 Ordering::AcqRel,
+                                                 Ordering::Acquire) {
+                Ok(_) => return true,
+                Err(current) => n = current,
+            }
         }
     }
 
-    fn push_top(top: &AtomicStampedPtr<Node<T>>, node: *mut Node<T>) {
+    fn push_top(&self,
+                top: &AtomicStampedPtr<Node<T>>,
+                node: *mut Node<T>,
+                elimination: Option<&Elimination<T>>) {
+        // The node is published by the CAS on `top`, which is a `lock cmpxchg16b`, or
+        // SeqCst in the fallback, so it orders like a SeqCst RMW: the writes of `data` and
+        // `next` before it happen before anything a thread does after loading the node
+        // from `top`. That alone makes `pop_top` sound. `next` is also stored with Release
+        // and loaded with Acquire, so a traversal that reaches a node through the `next`
+        // of another one, rather than from `top`, sees it fully written too.
+        let mut backoff = self.backoff();
         loop {
             let (p, v) = top.load();
             unsafe {
-                (*node).next = p;
+                (*node).next.store(p, Ordering::Release);
+            }
+            if top.compare_exchange((p, v), node).is_ok() {
+                break;
             }
-            if let Ok(_) = top.compare_exchange((p, v), node) {
+            if elimination.is_some_and(|e| e.offer(node)) {
                 break;
             }
+            self.stats.retried();
+            backoff.snooze();
         }
     }
 
-    fn pop_top(top: &AtomicStampedPtr<Node<T>>) -> *mut Node<T> {
+    fn pop_top(&self,
+               top: &AtomicStampedPtr<Node<T>>,
+               elimination: Option<&Elimination<T>>)
+               -> *mut Node<T> {
+        let hazard = self.reclaim.acquire();
+        let mut backoff = self.backoff();
         loop {
-            let (p, v) = top.load();
-            if p.is_null() {
+            if let Ok(p) = Self::try_pop_top(top, &hazard) {
                 return p;
             }
-            let n = unsafe { (*p).next };
-            if let Ok(_) = top.compare_exchange((p, v), n) {
-                return p;
+            if let Some(e) = elimination {
+                let node = e.take();
+                if !node.is_null() {
+                    self.stats.eliminated();
+                    return node;
+                }
             }
+            self.stats.retried();
+            backoff.snooze();
         }
     }
 
-    fn release(top: &AtomicStampedPtr<Node<T>>) {
-        let (mut p, _) = top.load();
-        while !p.is_null() {
-            let d = p;
-            unsafe {
-                p = (*p).next;
-                drop(Box::from_raw(d));
+    // Detach the first node with a single CAS, fail if another thread got in the way.
+    fn try_pop_top(top: &AtomicStampedPtr<Node<T>>, hazard: &R::Guard<'_>) -> Result<*mut Node<T>, ()> {
+        let (p, v) = top.load();
+        if p.is_null() {
+            return Ok(p);
+        }
+        // Another thread may pop and free `p` at any moment, so it must be protected
+        // before reading its `next`.
+        hazard.protect(p);
+        if top.load() != (p, v) {
+            return Err(());
+        }
+        let n = unsafe { (*p).next.load(Ordering::Acquire) };
+        top.compare_exchange((p, v), n).map(|_| p).map_err(|_| ())
+    }
+
+    // Free every node of the lists. Nodes are always freed through `free_node`, so a
+    // value still left in one, even in the trash, is dropped with it. If such a drop
+    // panics, the nodes left are freed while unwinding, and a second panic aborts.
+    fn release(&self) {
+        struct Lists<'a, T: 'a, R: Reclaim + 'a, A: NodeAlloc + 'a> {
+            stack: &'a ConcurrentStack<T, R, A>,
+            heads: [*mut Node<T>; 3],
+        }
+
+        impl<'a, T, R: Reclaim, A: NodeAlloc> Lists<'a, T, R, A> {
+            fn free(&mut self) {
+                for p in &mut self.heads {
+                    while !p.is_null() {
+                        let d = *p;
+                        unsafe {
+                            *p = (*d).next.load(Ordering::Acquire);
+                            self.stack.free_node(d);
+                        }
+                    }
+                }
+            }
+        }
+
+        impl<'a, T, R: Reclaim, A: NodeAlloc> Drop for Lists<'a, T, R, A> {
+            fn drop(&mut self) {
+                self.free();
             }
         }
+
+        let mut lists = Lists {
+            stack: self,
+            heads: [self.top.load().0, self.trash.load().0, self.recent.load().0],
+        };
+        lists.free();
     }
 
+    // Free a node no other thread reads any more, then drop what is left in it, so the
+    // node is freed even if the drop panics.
+    unsafe fn free_node(&self, node: *mut Node<T>) {
+        let Node { data, .. } = core::ptr::read(node);
+        self.alloc.dealloc(node as *mut u8, Self::node_layout());
+        drop(data);
+    }
+
+    // Recycle a detached node, or free it once the trash is full or the recycle policy
+    // says so. A node still protected by a hazard pointer, or while a traversal is
+    // running, is recycled regardless, as freeing it could pull the memory from under
+    // the reader. Without recycling such a node is only parked in the trash until
+    // `shrink` or drop.
     fn put_trash(&self, node: *mut Node<T>) {
-        Self::push_top(&self.trash, node);
+        if self.retire(node) {
+            return;
+        }
+        #[cfg(feature = "std")]
+        {
+            // Only a node that could be freed is ours alone, and may go to the cache.
+            if RECYCLE && self.local_cache && self.reclaim.may_free(node) &&
+               local::put(Self::node_layout(), node as *mut u8) {
+                self.stats.recycled();
+                return;
+            }
+        }
+        if (!RECYCLE || !self.should_recycle()) && self.reclaim.may_free(node) {
+            unsafe {
+                self.free_node(node);
+            }
+            return;
+        }
+        self.stats.recycled();
+        self.trash_len.fetch_add(1, Ordering::Relaxed);
+        let list = if self.fifo_trash { &self.recent } else { &*self.trash };
+        self.push_top(list, node, None);
     }
 
-    fn pick_trash(&self) -> *mut Node<T> {
-        Self::pop_top(&self.trash)
+    // Leave freeing a detached node without value to the backend, if it can.
+    fn retire(&self, node: *mut Node<T>) -> bool {
+        A::GLOBAL && self.reclaim.defer_free(node as *mut u8, Self::node_layout())
     }
 
-    fn do_push(&self, raw: T) {
-        let mut node = self.pick_trash();
+    fn should_recycle(&self) -> bool {
+        let trash_len = self.trash_len.load(Ordering::Relaxed);
+        match self.recycle {
+            Some(ref policy) => policy.should_recycle(trash_len),
+            None => trash_len < self.trash_limit,
+        }
+    }
+
+    fn alloc_node(&self) -> *mut Node<T> {
+        let node = self.try_alloc_node();
         if node.is_null() {
-            node = Box::into_raw(Box::new(Node {
-                data: None,
-                next: std::ptr::null_mut(),
-            }));
+            alloc::alloc::handle_alloc_error(Self::node_layout());
         }
-        unsafe {
-            (*node).data = Some(raw);
+        node
+    }
+
+    fn fill_trash(&self, n: usize) {
+        if !RECYCLE || Self::is_zst() {
+            return;
+        }
+        for _ in 0..n {
+            let node = self.alloc_node();
+            self.stats.allocated();
+            self.trash_len.fetch_add(1, Ordering::Relaxed);
+            self.push_top(&self.trash, node, None);
         }
-        Self::push_top(&self.top, node);
     }
 
-    /// Push a value on the top of stack.
-    pub fn push(&self, raw: T) {
-        self.do_push(raw);
+    // No fence is needed around reuse. The node leaves the trash with a CAS of its head,
+    // a SeqCst RMW, so the new value is written after the node is ours. The CAS that
+    // puts it on `top` is one too and the popper loads `top` before reading the value,
+    // so the value is visible to it. The popper that trashed the node moved the old
+    // value out before its own CAS on the trash head, which this CAS reads from.
+    fn pick_trash(&self) -> *mut Node<T> {
+        if !RECYCLE {
+            return core::ptr::null_mut();
+        }
+        let mut node = self.pop_top(&self.trash, None);
+        if node.is_null() && self.fifo_trash {
+            node = self.refill_trash();
+        }
+        if !node.is_null() {
+            self.trash_len.fetch_sub(1, Ordering::Relaxed);
+        }
+        node
     }
 
-    /// Pop a value from the top of stack, if no available， return None.
-    pub fn pop(&self) -> Option<T> {
-        let node = Self::pop_top(&self.top);
-        if node.is_null() {
-            None
-        } else {
-            let mut v = None;
-            std::mem::swap(&mut v, unsafe { &mut (*node).data });
-            self.put_trash(node);
-            v
+    // Take a node from the cache of this thread, refilling it from the trash if empty.
+    #[cfg(feature = "std")]
+    fn pick_local(&self) -> *mut Node<T> {
+        if !RECYCLE || !self.local_cache {
+            return core::ptr::null_mut();
+        }
+        let p = local::take(Self::node_layout()) as *mut Node<T>;
+        if !p.is_null() {
+            // Whatever node of this layout was there held nothing that needs dropping.
+            unsafe {
+                core::ptr::write(p,
+                                 Node {
+                                     data: UnsafeCell::new(None),
+                                     next: AtomicPtr::new(core::ptr::null_mut()),
+                                     live: AtomicBool::new(false),
+                                 });
+            }
+            return p;
         }
+        // Detach a few nodes at once, keep the first and cache those nobody else reads.
+        // That leaves room for the first one once it is popped again.
+        let room = local::room();
+        if room == 0 {
+            return p;
+        }
+        let first = match self.try_pop_chain(&self.trash, room, false) {
+            Ok(first) if !first.is_null() => first,
+            _ => return core::ptr::null_mut(),
+        };
+        let mut p = unsafe { (*first).next.load(Ordering::Acquire) };
+        let mut taken = 1;
+        while !p.is_null() {
+            let node = p;
+            p = unsafe { (*node).next.load(Ordering::Acquire) };
+            if self.reclaim.may_free(node) && local::put(Self::node_layout(), node as *mut u8) {
+                taken += 1;
+            } else {
+                self.push_top(&self.trash, node, None);
+            }
+        }
+        self.trash_len.fetch_sub(taken, Ordering::Relaxed);
+        first
     }
 
-    /// Check if stack is empty.
-    pub fn empty(&self) -> bool {
-        self.top.load().0.is_null()
+    #[cfg(not(feature = "std"))]
+    fn pick_local(&self) -> *mut Node<T> {
+        core::ptr::null_mut()
     }
-}
 
-impl<T> Drop for ConcurrentStack<T> {
-    fn drop(&mut self) {
-        Self::release(&self.top);
-        Self::release(&self.trash);
+    // Move the nodes trashed recently over to the trash, oldest first, and return the
+    // oldest one instead of putting it there.
+    fn refill_trash(&self) -> *mut Node<T> {
+        let mut p = self.recent.swap(core::ptr::null_mut());
+        if p.is_null() {
+            return p;
+        }
+        // The chain is ours now. Readers still holding one of its nodes from an earlier
+        // trip through the trash may read `next`, but their CAS fails on the stamp.
+        let newest = p;
+        let mut reversed: *mut Node<T> = core::ptr::null_mut();
+        while !p.is_null() {
+            let node = p;
+            unsafe {
+                p = (*node).next.load(Ordering::Acquire);
+                (*node).next.store(reversed, Ordering::Release);
+            }
+            reversed = node;
+        }
+        let oldest = reversed;
+        let rest = unsafe { (*oldest).next.load(Ordering::Relaxed) };
+        if !rest.is_null() {
+            self.splice_onto(&self.trash, rest, newest);
+        }
+        oldest
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::ConcurrentStack;
-    use std::sync::Arc;
-    use std::thread;
+    // Wait until no other thread reads the value of a node detached from `top`.
+    fn claim(&self, node: *mut Node<T>) {
+        unsafe {
+            (*node).live.store(false, Ordering::SeqCst);
+        }
+        // A concurrent peek or traversal may still be cloning the value.
+        self.reclaim.wait_readers(node);
+    }
 
-    #[test]
-    fn lock_free_stack_single_thread() {
-        let stack = ConcurrentStack::new();
-        stack.push(1);
-        stack.push(2);
-        stack.push(3);
-        assert_eq!(stack.pop(), Some(3));
-        assert_eq!(stack.pop(), Some(2));
-        stack.push(4);
-        assert_eq!(stack.pop(), Some(4));
-        assert_eq!(stack.pop(), Some(1));
+    // Wait, once for the whole chain, until no other thread reads the value of one of
+    // its nodes, and return how many there are.
+    fn claim_chain(&self, first: *mut Node<T>) -> usize {
+        let mut n = 0;
+        for p in Self::chain(first) {
+            unsafe {
+                (*p).live.store(false, Ordering::SeqCst);
+            }
+            n += 1;
+        }
+        if n > 0 {
+            self.reclaim.wait_readers_all(Self::chain(first));
+        }
+        n
     }
 
-    #[test]
-    fn multi_thread_sum() {
-        let stack = Arc::new(ConcurrentStack::new());
+    // The nodes of a detached chain, which must not change while iterating.
+    fn chain(first: *mut Node<T>) -> impl Iterator<Item = *mut Node<T>> {
+        let first = if first.is_null() { None } else { Some(first) };
+        core::iter::successors(first, |&p| {
+            let next = unsafe { (*p).next.load(Ordering::Acquire) };
+            if next.is_null() {
+                None
+            } else {
+                Some(next)
+            }
+        })
+    }
 
-        let input_p = (0..10)
-                          .map(|_| {
-                              let stack = stack.clone();
-                              thread::spawn(move || {
-                                  for i in 0..100 {
-                                      stack.push(i);
-                                  }
-                              })
-                          })
-                          .collect::<Vec<_>>();
+    // Move the value out of a node detached from `top` and recycle the node.
+    //
+    // Between the detach and `put_trash` the node is on neither list, so no thread can
+    // load it any more. Those that loaded it before are poppers, which only read `next`
+    // and then fail their CAS on the stamp, and readers of `data`, which `claim` waits
+    // for. No push can get the node before it is in the trash, after the value is out.
+    fn take(&self, node: *mut Node<T>) -> Option<T> {
+        self.claim(node);
+        self.take_claimed(node)
+    }
 
+    // Like `take`, for a node already claimed.
+    fn take_claimed(&self, node: *mut Node<T>) -> Option<T> {
+        let v = unsafe { (*node).data.with_mut(|d| (*d).take()) };
+        self.put_trash(node);
+        v
+    }
 
-        let mut sum = 0;
+    // Like `alloc_node`, but return null instead of aborting when out of memory.
+    fn try_alloc_node(&self) -> *mut Node<T> {
+        let node = self.alloc.alloc(Self::node_layout()) as *mut Node<T>;
+        if !node.is_null() {
+            unsafe {
+                core::ptr::write(node,
+                                 Node {
+                                     data: UnsafeCell::new(None),
+                                     next: AtomicPtr::new(core::ptr::null_mut()),
+                                     live: AtomicBool::new(false),
+                                 });
+            }
+        }
+        node
+    }
 
-        let output_p = {
-            let stack = stack.clone();
-            thread::spawn(move || {
-                loop {
-                    if let Some(i) = stack.pop() {
-                        sum += i;
-                    } else if sum == 49500 {
-                        break;
+    fn new_node(&self, raw: T) -> *mut Node<T> {
+        let mut node = self.pick_local();
+        if node.is_null() {
+            node = self.pick_trash();
+        }
+        if node.is_null() {
+            node = self.alloc_node();
+            self.stats.allocated();
+        }
+        Self::set_value(node, raw);
+        node
+    }
+
+    fn set_value(node: *mut Node<T>, raw: T) {
+        unsafe {
+            (*node).data.with_mut(|d| *d = Some(raw));
+            (*node).live.store(true, Ordering::Release);
+        }
+    }
+
+    fn do_push(&self, raw: T) {
+        let node = self.new_node(raw);
+        self.push_top(&self.top, node, self.elimination.as_ref());
+    }
+
+    // Panic if following `next` from `p` never reaches null. Only for lists no other
+    // thread changes, and only called in debug builds: a cycle would otherwise make
+    // `pop_all`, `release` and the like loop forever.
+    fn assert_acyclic(p: *mut Node<T>) {
+        let next = |p: *mut Node<T>| unsafe { (*p).next.load(Ordering::Acquire) };
+        let (mut slow, mut fast) = (p, p);
+        while !fast.is_null() {
+            fast = next(fast);
+            if fast.is_null() {
+                return;
+            }
+            fast = next(fast);
+            slow = next(slow);
+            if slow == fast {
+                panic!("cycle in the node list of a ConcurrentStack");
+            }
+        }
+    }
+
+    // Link the chain from `first` to `last` on the top of stack with one CAS.
+    fn splice(&self, first: *mut Node<T>, last: *mut Node<T>) {
+        self.splice_onto(&self.top, first, last)
+    }
+
+    fn splice_onto(&self,
+                   list: &AtomicStampedPtr<Node<T>>,
+                   first: *mut Node<T>,
+                   last: *mut Node<T>) {
+        // The chain is still ours, and `next` of `last` null.
+        if cfg!(debug_assertions) {
+            Self::assert_acyclic(first);
+        }
+        let mut backoff = self.backoff();
+        loop {
+            let (p, v) = list.load();
+            unsafe {
+                (*last).next.store(p, Ordering::Release);
+            }
+            if list.compare_exchange((p, v), first).is_ok() {
+                break;
+            }
+            self.stats.retried();
+            backoff.snooze();
+        }
+    }
+
+    // Every push panics on a closed stack, see `close`.
+    fn assert_open(&self) {
+        if self.closed.load(Ordering::Relaxed) {
+            panic!("push to a closed ConcurrentStack");
+        }
+    }
+
+    /// Mark the stack as closed: no value is pushed any more, and consumers popping
+    /// with `pop_or_closed` learn so once the values left are gone.
+    ///
+    /// Every push after this panics. Producers should close the stack once they are
+    /// done, a push running at the same time as `close` may either panic or still land,
+    /// possibly after a consumer already saw `PopResult::Closed`. Pops keep working as
+    /// before.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+    }
+
+    /// Check if `close` was called.
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Push a value on the top of stack.
+    ///
+    /// Takes effect at the CAS linking the node, see the crate docs on linearization.
+    ///
+    /// # Panics
+    ///
+    /// If the stack was closed, like every other push.
+    pub fn push(&self, raw: T) {
+        self.assert_open();
+        if Self::is_zst() {
+            core::mem::forget(raw);
+        } else {
+            self.do_push(raw);
+        }
+        self.len.fetch_add(1, Ordering::Release);
+        self.stats.pushed(1);
+    }
+
+    /// Push every value of `iter`, the first one ending up deepest.
+    ///
+    /// The values are linked into a chain off the stack first, then the chain is put on
+    /// the top with a single CAS. Other threads contend with one CAS for the whole batch
+    /// instead of one per value, and never see part of it.
+    pub fn push_iter<I: IntoIterator<Item = T>>(&self, iter: I) {
+        self.assert_open();
+        if Self::is_zst() {
+            let n = iter.into_iter().map(core::mem::forget).count();
+            self.len.fetch_add(n, Ordering::Release);
+            self.stats.pushed(n);
+            return;
+        }
+        let mut first: *mut Node<T> = core::ptr::null_mut();
+        let mut last = first;
+        let mut n = 0;
+        for v in iter {
+            let node = self.new_node(v);
+            unsafe {
+                (*node).next.store(first, Ordering::Release);
+            }
+            if last.is_null() {
+                last = node;
+            }
+            first = node;
+            n += 1;
+        }
+        if !first.is_null() {
+            self.splice(first, last);
+            self.len.fetch_add(n, Ordering::Release);
+            self.stats.pushed(n);
+        }
+    }
+
+    /// Push a copy of every value of `items`, `items[0]` ending up deepest.
+    ///
+    /// This is `push_iter` over copies of the values, so the whole slice goes on the
+    /// top with a single CAS too, and stays usable afterwards.
+    pub fn extend_from_slice(&self, items: &[T])
+    where
+        T: Copy,
+    {
+        self.push_iter(items.iter().copied());
+    }
+
+    /// Push a value on the top of stack unless it already holds `capacity` values, in
+    /// which case the value is handed back.
+    pub fn try_push(&self, raw: T) -> Result<(), T> {
+        self.assert_open();
+        if !self.reserve_len() {
+            return Err(raw);
+        }
+        if Self::is_zst() {
+            core::mem::forget(raw);
+        } else {
+            self.do_push(raw);
+        }
+        self.stats.pushed(1);
+        Ok(())
+    }
+
+    /// Push a value on the top of stack, handing it back if there is no memory for it.
+    ///
+    /// A recycled node is used if there is one, from the cache of the thread first like
+    /// for `push`. Otherwise a new node is allocated, and failing that the value is
+    /// returned instead of aborting the process like `push` does. Only the node
+    /// allocation is fallible, the hazard slot a thread gets on its first operation on
+    /// the stack is still allocated the usual way.
+    pub fn try_push_alloc(&self, raw: T) -> Result<(), T> {
+        self.assert_open();
+        if Self::is_zst() {
+            self.push(raw);
+            return Ok(());
+        }
+        let mut node = self.pick_local();
+        if node.is_null() {
+            node = self.pick_trash();
+        }
+        if node.is_null() {
+            node = self.try_alloc_node();
+            if node.is_null() {
+                return Err(raw);
+            }
+            self.stats.allocated();
+        }
+        Self::set_value(node, raw);
+        self.push_top(&self.top, node, self.elimination.as_ref());
+        self.len.fetch_add(1, Ordering::Release);
+        self.stats.pushed(1);
+        Ok(())
+    }
+
+    /// Push `raw` only if the stack is empty, and hand it back otherwise.
+    ///
+    /// The check and the push are one CAS of `top` from empty to the new node, so of
+    /// several threads racing on an empty stack exactly one succeeds. A value pushed
+    /// and popped again in the meantime only makes the CAS retry.
+    pub fn push_if_empty(&self, raw: T) -> Result<(), T> {
+        self.assert_open();
+        if Self::is_zst() {
+            if self.len.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire).is_err() {
+                return Err(raw);
+            }
+            core::mem::forget(raw);
+            self.stats.pushed(1);
+            return Ok(());
+        }
+        let node = self.new_node(raw);
+        let mut backoff = self.backoff();
+        loop {
+            let (p, v) = self.top.load();
+            if !p.is_null() {
+                break;
+            }
+            unsafe {
+                (*node).next.store(p, Ordering::Release);
+            }
+            if self.top.compare_exchange((p, v), node).is_ok() {
+                self.len.fetch_add(1, Ordering::Release);
+                self.stats.pushed(1);
+                return Ok(());
+            }
+            self.stats.retried();
+            backoff.snooze();
+        }
+        Err(self.unpublish(node))
+    }
+
+    /// Push `raw` unless it equals the value on the top of stack, and return whether it
+    /// was pushed.
+    ///
+    /// Only the top value is compared, an equal value deeper in the stack does not
+    /// prevent the push. The comparison holds when the push lands: the CAS putting
+    /// `raw` on the top fails if the top changed since, and the check is then redone
+    /// against the new top.
+    pub fn push_dedup(&self, raw: T) -> bool
+    where
+        T: PartialEq + Sync,
+        R: SharedRead,
+    {
+        self.assert_open();
+        if Self::is_zst() {
+            return self.push_dedup_zst(raw);
+        }
+        let node = self.new_node(raw);
+        let hazard = self.reclaim.acquire();
+        let mut backoff = self.backoff();
+        loop {
+            let (p, v) = self.top.load();
+            if !p.is_null() {
+                // Same as in `peek`, `p` must be protected to read its value.
+                hazard.protect(p);
+                if self.top.load() != (p, v) {
+                    backoff.snooze();
+                    continue;
+                }
+                if unsafe { (*p).data.with(|a| (*node).data.with(|b| *a == *b)) } {
+                    drop(hazard);
+                    drop(self.unpublish(node));
+                    return false;
+                }
+            }
+            unsafe {
+                (*node).next.store(p, Ordering::Release);
+            }
+            if self.top.compare_exchange((p, v), node).is_ok() {
+                break;
+            }
+            self.stats.retried();
+            backoff.snooze();
+        }
+        self.len.fetch_add(1, Ordering::Release);
+        self.stats.pushed(1);
+        true
+    }
+
+    fn push_dedup_zst(&self, raw: T) -> bool
+    where
+        T: PartialEq,
+    {
+        let mut n = self.len.load(Ordering::Acquire);
+        loop {
+            if Self::clamp_len(n) > 0 {
+                let top = core::mem::ManuallyDrop::new(unsafe { Self::zst_value() });
+                if raw == *top {
+                    return false;
+                }
+            }
+            match self.len.compare_exchange_weak(n,
+                                                 n.wrapping_add(1),
+                                                 Ordering::AcqRel,
+                                                 Ordering::Acquire) {
+                Ok(_) => break,
+                Err(current) => n = current,
+            }
+        }
+        core::mem::forget(raw);
+        self.stats.pushed(1);
+        true
+    }
+
+    // Move the value back out of a node that was never published, and recycle the node.
+    fn unpublish(&self, node: *mut Node<T>) -> T {
+        // Nobody else can be reading it.
+        let raw = unsafe {
+            (*node).live.store(false, Ordering::Relaxed);
+            (*node).data.with_mut(|d| (*d).take())
+        };
+        self.put_trash(node);
+        raw.unwrap()
+    }
+
+    /// Pop a value from the top of stack, if no available， return None.
+    ///
+    /// Takes effect at the CAS detaching the node, or at the load finding the stack
+    /// empty, see the crate docs on linearization.
+    pub fn pop(&self) -> Option<T> {
+        if Self::is_zst() {
+            return self.pop_zst();
+        }
+        let node = self.pop_top(&self.top, self.elimination.as_ref());
+        self.finish_pop(node)
+    }
+
+    /// Pop the value on the top of stack, or make a new one with `f` if the stack is
+    /// empty.
+    ///
+    /// The new value is handed out, not pushed: with values returned to the stack once
+    /// done with, this is the way to get one from a pool.
+    pub fn pop_or_insert_with<F: FnOnce() -> T>(&self, f: F) -> T {
+        self.pop().unwrap_or_else(f)
+    }
+
+    /// Pop the value on the top of stack if `pred` accepts it.
+    ///
+    /// The top value is popped first and pushed back when `pred` rejects it, so other
+    /// threads may see it missing for a moment. Values pushed meanwhile end up below
+    /// it, and a concurrent pop may get another value than it would have otherwise.
+    /// Without concurrent pushes and pops the stack is left unchanged on rejection.
+    pub fn pop_if<F: FnMut(&T) -> bool>(&self, mut pred: F) -> Option<T> {
+        if Self::is_zst() {
+            if self.take_zst(1) == 0 {
+                return None;
+            }
+            let v = unsafe { Self::zst_value() };
+            if pred(&v) {
+                self.stats.popped(1);
+                return Some(v);
+            }
+            core::mem::forget(v);
+            self.len.fetch_add(1, Ordering::Release);
+            return None;
+        }
+        let node = self.pop_top(&self.top, self.elimination.as_ref());
+        if node.is_null() {
+            return None;
+        }
+        self.claim(node);
+        let accepted = unsafe {
+            (*node).data.with(|d| match *d {
+                Some(ref v) => pred(v),
+                None => true,
+            })
+        };
+        if accepted {
+            self.finish_pop(node)
+        } else {
+            unsafe {
+                (*node).live.store(true, Ordering::Release);
+            }
+            self.push_top(&self.top, node, None);
+            None
+        }
+    }
+
+    /// Replace the value on the top of stack with `raw` and return the old one, or push
+    /// `raw` and return `None` if the stack is empty.
+    ///
+    /// A new node for `raw` takes the place of the top node with a single CAS, so no
+    /// other thread ever sees the stack without either value on it.
+    pub fn swap_top(&self, raw: T) -> Option<T> {
+        self.assert_open();
+        if Self::is_zst() {
+            return self.swap_top_zst(raw);
+        }
+        let node = self.new_node(raw);
+        let old = {
+            let hazard = self.reclaim.acquire();
+            let mut backoff = self.backoff();
+            loop {
+                let (p, v) = self.top.load();
+                let mut next = core::ptr::null_mut();
+                if !p.is_null() {
+                    // Same as in `try_pop_top`, `p` must be protected to read its `next`.
+                    hazard.protect(p);
+                    if self.top.load() != (p, v) {
+                        backoff.snooze();
+                        continue;
+                    }
+                    next = unsafe { (*p).next.load(Ordering::Acquire) };
+                }
+                unsafe {
+                    (*node).next.store(next, Ordering::Release);
+                }
+                if self.top.compare_exchange((p, v), node).is_ok() {
+                    break p;
+                }
+                self.stats.retried();
+                backoff.snooze();
+            }
+        };
+        self.stats.pushed(1);
+        if old.is_null() {
+            self.len.fetch_add(1, Ordering::Release);
+            return None;
+        }
+        self.stats.popped(1);
+        self.take(old)
+    }
+
+    // The old top is as good as the new one, only an empty stack changes.
+    fn swap_top_zst(&self, raw: T) -> Option<T> {
+        if self.len.compare_exchange(0, 1, Ordering::AcqRel, Ordering::Acquire).is_ok() {
+            core::mem::forget(raw);
+            self.stats.pushed(1);
+            None
+        } else {
+            Some(raw)
+        }
+    }
+
+    /// Pop values from the top of stack as long as `pred` accepts them, top first.
+    ///
+    /// This is `pop_if` repeated until it fails, so each value is popped on its own and
+    /// the first rejected one is pushed back. The values returned were each on the top
+    /// when popped, but another thread may push or pop between two of them, so they
+    /// need not have been a contiguous run of the stack at any instant.
+    pub fn pop_while<F: FnMut(&T) -> bool>(&self, mut pred: F) -> Vec<T> {
+        let mut values = Vec::new();
+        while let Some(v) = self.pop_if(&mut pred) {
+            values.push(v);
+        }
+        values
+    }
+
+    /// Try once to pop a value from the top of stack.
+    ///
+    /// Return `Ok(None)` if the stack is empty, and `Err(())` if another thread changed
+    /// the top meanwhile, in which case the caller can back off and retry. `pop` is the
+    /// same attempt retried until it goes through.
+    #[allow(clippy::result_unit_err)]
+    pub fn try_pop(&self) -> Result<Option<T>, ()> {
+        if Self::is_zst() {
+            return Ok(self.pop_zst());
+        }
+        let node = Self::try_pop_top(&self.top, &self.reclaim.acquire())?;
+        Ok(self.finish_pop(node))
+    }
+
+    /// Pop a value, telling an empty stack apart from one that is closed and drained.
+    ///
+    /// `Closed` is only returned once the stack was closed and every value pushed before
+    /// the `close` has been popped, so a consumer can stop there. While another thread
+    /// has the values off the list for a moment, like `try_into_vec` does, they still
+    /// count as on the stack and this returns `Empty`.
+    pub fn pop_or_closed(&self) -> PopResult<T> {
+        // Loaded first: if the stack was closed already, every push before the close is
+        // visible to the pop below.
+        let closed = self.is_closed();
+        match self.pop() {
+            Some(v) => PopResult::Value(v),
+            // Values off the list but not popped are still counted, unlike `is_empty`
+            // this checks the counter.
+            None if closed && Self::clamp_len(self.len.load(Ordering::Acquire)) == 0 => {
+                PopResult::Closed
+            }
+            None => PopResult::Empty,
+        }
+    }
+
+    /// Pop a value like `pop`, but give the CPU away as soon as a few attempts fail.
+    ///
+    /// `pop` spins for a growing while before it starts yielding, which wastes the time
+    /// slice when there are more threads than cores and the thread in the way is not
+    /// even running. This one yields after `YIELD_AFTER` failed attempts in a row. It
+    /// still retries until it gets a value, and returns `None` only for an empty stack.
+    #[cfg(feature = "std")]
+    pub fn pop_yield(&self) -> Option<T> {
+        let mut failures = 0;
+        loop {
+            if let Ok(v) = self.try_pop() {
+                return v;
+            }
+            failures += 1;
+            if failures >= YIELD_AFTER {
+                std::thread::yield_now();
+            } else {
+                spin_loop();
+            }
+        }
+    }
+
+    fn pop_zst(&self) -> Option<T> {
+        if self.take_zst(1) == 0 {
+            return None;
+        }
+        self.stats.popped(1);
+        Some(unsafe { Self::zst_value() })
+    }
+
+    fn finish_pop(&self, node: *mut Node<T>) -> Option<T> {
+        if node.is_null() {
+            None
+        } else {
+            self.len.fetch_sub(1, Ordering::Release);
+            self.stats.popped(1);
+            self.take(node)
+        }
+    }
+
+    /// Drop every value on the stack.
+    ///
+    /// The whole chain is detached at once, values pushed after that stay on the stack.
+    pub fn clear(&self) {
+        self.count_drained();
+    }
+
+    /// Drop every value on the stack like `clear`, and return how many there were.
+    ///
+    /// Unlike `pop_all().len()` this collects nothing, the values are dropped one by
+    /// one as they come off the detached chain.
+    pub fn count_drained(&self) -> usize {
+        if Self::is_zst() {
+            let n = self.take_zst(usize::MAX);
+            self.stats.popped(n);
+            for _ in 0..n {
+                drop(unsafe { Self::zst_value() });
+            }
+            return n;
+        }
+        self.drain_chain(self.top.swap(core::ptr::null_mut()), drop)
+    }
+
+    /// Keep only the values `f` accepts and drop the others, the kept ones stay in order.
+    ///
+    /// `f` is called from the top down. Needing exclusive access, this relinks the
+    /// list in place without any CAS.
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        if Self::is_zst() {
+            let n = Self::clamp_len(self.len.load(Ordering::Acquire));
+            let mut kept = 0;
+            for _ in 0..n {
+                let v = unsafe { Self::zst_value() };
+                if f(&v) {
+                    core::mem::forget(v);
+                    kept += 1;
+                }
+            }
+            self.len.store(kept, Ordering::Release);
+            self.stats.popped(n - kept);
+            return;
+        }
+        let mut p = self.top.swap(core::ptr::null_mut());
+        let mut first = core::ptr::null_mut();
+        let mut last: *mut Node<T> = core::ptr::null_mut();
+        let mut removed = 0;
+        while !p.is_null() {
+            let node = p;
+            p = unsafe { (*node).next.load(Ordering::Relaxed) };
+            let keep = unsafe { (*node).data.with(|d| (*d).as_ref().is_some_and(&mut f)) };
+            if keep {
+                if last.is_null() {
+                    first = node;
+                } else {
+                    unsafe {
+                        (*last).next.store(node, Ordering::Relaxed);
+                    }
+                }
+                last = node;
+            } else {
+                drop(self.take(node));
+                removed += 1;
+            }
+        }
+        if !last.is_null() {
+            unsafe {
+                (*last).next.store(core::ptr::null_mut(), Ordering::Relaxed);
+            }
+        }
+        if cfg!(debug_assertions) {
+            Self::assert_acyclic(first);
+        }
+        self.top.swap(first);
+        self.len.fetch_sub(removed, Ordering::Release);
+        self.stats.popped(removed);
+    }
+
+    /// Pop values lazily through the returned iterator, and drop whatever is left on
+    /// the stack once it is dropped.
+    ///
+    /// Each `next` is a `pop`, so other threads may keep using the stack meanwhile.
+    /// Dropping the guard clears the stack like `clear`, including values other threads
+    /// pushed during the drain.
+    pub fn drain(&self) -> DrainGuard<'_, T, R, A> {
+        DrainGuard { stack: self }
+    }
+
+    /// Pop every value on the stack, top first.
+    ///
+    /// The whole chain is detached with a single swap of `top` instead of one CAS per
+    /// value. Values pushed after that are not included.
+    pub fn pop_all(&self) -> Vec<T> {
+        if Self::is_zst() {
+            return self.pop_n(usize::MAX);
+        }
+        let mut values = Vec::new();
+        self.drain_chain(self.top.swap(core::ptr::null_mut()), |v| values.push(v));
+        values
+    }
+
+    /// Pop every value on the stack like `pop_all`, top first, but feed them into `sink`
+    /// instead of a new `Vec`.
+    ///
+    /// The values go in one at a time while the detached chain is walked, so the sink
+    /// sees each value as soon as it is out of its node.
+    pub fn drain_to<C: Extend<T>>(&self, sink: &mut C) {
+        if Self::is_zst() {
+            let n = self.take_zst(usize::MAX);
+            self.stats.popped(n);
+            sink.extend((0..n).map(|_| unsafe { Self::zst_value() }));
+            return;
+        }
+        self.drain_chain(self.top.swap(core::ptr::null_mut()), |v| sink.extend(Some(v)));
+    }
+
+    /// Detach every value on the stack at once and hand over the nodes holding them.
+    ///
+    /// Like `pop_all` the chain is detached with a single swap of `top`, but the values
+    /// stay in their nodes until the returned `StackChain` yields them, top first. Other
+    /// threads still reading the nodes, like a `peek`, are waited for before this
+    /// returns, so the chain no longer borrows the stack. Its nodes are freed instead of
+    /// recycled.
+    pub fn take_chain(&self) -> StackChain<T, A>
+    where
+        A: Clone,
+    {
+        let chain = |head, len| {
+            StackChain { head, len, alloc: self.alloc.clone(), _values: PhantomData }
+        };
+        if Self::is_zst() {
+            let n = self.take_zst(usize::MAX);
+            self.stats.popped(n);
+            return chain(core::ptr::null_mut(), n);
+        }
+        let head = self.top.swap(core::ptr::null_mut());
+        if head.is_null() {
+            return chain(head, 0);
+        }
+        // The nodes are freed by the chain, so walkers must be done with them too.
+        self.reclaim.wait_walkers();
+        let n = self.claim_chain(head);
+        self.len.fetch_sub(n, Ordering::Release);
+        self.stats.popped(n);
+        chain(head, n)
+    }
+
+    /// Move every value of `other` onto the top of this stack, keeping their order.
+    ///
+    /// The chain of `other` is detached with a single swap and linked on the top with
+    /// a single CAS, without moving any value or allocating. Values pushed to `other`
+    /// meanwhile stay there. Threads still reading nodes of `other`, like a `peek`, are
+    /// waited for before the chain is handed over.
+    ///
+    /// Appending a stack to itself does nothing, its values already are on its top.
+    pub fn append(&self, other: &ConcurrentStack<T, R, A>) {
+        // Detaching our own chain and splicing it back would move it above concurrent
+        // pushes and make `len` dip for a moment, for no gain.
+        if core::ptr::eq(self, other) {
+            return;
+        }
+        self.assert_open();
+        if Self::is_zst() {
+            let n = other.take_zst(usize::MAX);
+            other.stats.popped(n);
+            self.len.fetch_add(n, Ordering::Release);
+            self.stats.pushed(n);
+            return;
+        }
+        let first = other.top.swap(core::ptr::null_mut());
+        if first.is_null() {
+            return;
+        }
+        // From now on the nodes are only protected against readers of this stack.
+        other.reclaim.wait_walkers();
+        other.reclaim.wait_readers_all(Self::chain(first));
+        let mut last = first;
+        let mut n = 1;
+        for p in Self::chain(first).skip(1) {
+            last = p;
+            n += 1;
+        }
+        other.len.fetch_sub(n, Ordering::Release);
+        other.stats.popped(n);
+        self.splice(first, last);
+        self.len.fetch_add(n, Ordering::Release);
+        self.stats.pushed(n);
+    }
+
+    /// Keep the top `at` values and move those below into a new stack with the same
+    /// settings, in the same order. With `at` values or fewer, the new stack is empty.
+    ///
+    /// Like `Vec::split_off`, with the index counted from the top. The whole chain is
+    /// detached with a single swap, cut after its `at`-th node, and the top part linked
+    /// back with one CAS. Other threads see the stack empty meanwhile, values they push
+    /// then end up below the top part, and pushes or pops right before the split change
+    /// which values are the top `at`. Threads still reading nodes of the bottom part,
+    /// like a `peek`, are waited for before it is handed over.
+    pub fn split_off(&self, at: usize) -> ConcurrentStack<T, R, A>
+    where
+        A: Clone,
+    {
+        let split = self.empty_like();
+        if Self::is_zst() {
+            let n = self.take_zst(self.len().saturating_sub(at));
+            self.stats.popped(n);
+            split.len.store(n, Ordering::Release);
+            split.stats.pushed(n);
+            return split;
+        }
+        let first = self.top.swap(core::ptr::null_mut());
+        if first.is_null() {
+            return split;
+        }
+        let mut rest = first;
+        if at > 0 {
+            let mut last = first;
+            for _ in 1..at {
+                let next = unsafe { (*last).next.load(Ordering::Acquire) };
+                if next.is_null() {
+                    break;
+                }
+                last = next;
+            }
+            rest = unsafe { (*last).next.swap(core::ptr::null_mut(), Ordering::AcqRel) };
+            self.splice(first, last);
+        }
+        if rest.is_null() {
+            return split;
+        }
+        // Cut off, the rest is only reachable for those who were already reading it.
+        self.reclaim.wait_walkers();
+        self.reclaim.wait_readers_all(Self::chain(rest));
+        let n = Self::chain(rest).count();
+        self.len.fetch_sub(n, Ordering::Release);
+        self.stats.popped(n);
+        split.top.swap(rest);
+        split.len.store(n, Ordering::Release);
+        split.stats.pushed(n);
+        split
+    }
+
+    /// Pop up to `n` values, top first, fewer if the stack runs empty.
+    ///
+    /// The run of nodes is detached with a single CAS of `top`, retried as a whole if
+    /// another thread changes the top meanwhile.
+    pub fn pop_n(&self, n: usize) -> Vec<T> {
+        let mut values = Vec::new();
+        self.pop_batch_into(&mut values, n);
+        values
+    }
+
+    /// Pop about half the values on the stack, top first, for a thief in a work
+    /// stealing scheduler.
+    ///
+    /// Half is taken from `len`, rounded up, and then detached with a single CAS like
+    /// `pop_n`. Under concurrency the length may change between the two, so the thief
+    /// may get less, or rather more, than half of what is left.
+    pub fn steal_half(&self) -> Vec<T> {
+        self.pop_n(self.len().div_ceil(2))
+    }
+
+    /// Pop up to `max` values like `pop_n`, but append them to `buf` instead of a new
+    /// `Vec`, and return how many were popped.
+    ///
+    /// A consumer that clears and reuses the same buffer only allocates until it has
+    /// grown large enough.
+    pub fn pop_batch_into(&self, buf: &mut Vec<T>, max: usize) -> usize {
+        if Self::is_zst() {
+            let taken = self.take_zst(max);
+            self.stats.popped(taken);
+            buf.extend((0..taken).map(|_| unsafe { Self::zst_value() }));
+            return taken;
+        }
+        if max == 0 {
+            return 0;
+        }
+        let mut backoff = self.backoff();
+        let first = loop {
+            if let Ok(p) = self.try_pop_chain(&self.top, max, false) {
+                break p;
+            }
+            self.stats.retried();
+            backoff.snooze();
+        };
+        let before = buf.len();
+        self.drain_chain(first, |v| buf.push(v));
+        buf.len() - before
+    }
+
+    /// Pop exactly `n` values, top first, or none at all if there are fewer.
+    ///
+    /// The `n` nodes are detached with a single CAS like `pop_n`, retried as a whole
+    /// if another thread changes the top meanwhile. `None` means that at some instant
+    /// during the call the stack held fewer than `n` values, and it is left untouched.
+    pub fn pop_exact(&self, n: usize) -> Option<Vec<T>> {
+        if Self::is_zst() {
+            let mut have = self.len.load(Ordering::Acquire);
+            loop {
+                if Self::clamp_len(have) < n {
+                    return None;
+                }
+                match self.len.compare_exchange_weak(have,
+                                                     have - n,
+                                                     Ordering::AcqRel,
+                                                     Ordering::Acquire) {
+                    Ok(_) => break,
+                    Err(now) => have = now,
+                }
+            }
+            self.stats.popped(n);
+            return Some((0..n).map(|_| unsafe { Self::zst_value() }).collect());
+        }
+        let mut values = Vec::new();
+        if n == 0 {
+            return Some(values);
+        }
+        let mut backoff = self.backoff();
+        let first = loop {
+            if let Ok(p) = self.try_pop_chain(&self.top, n, true) {
+                break p;
+            }
+            self.stats.retried();
+            backoff.snooze();
+        };
+        if first.is_null() {
+            return None;
+        }
+        values.reserve(n);
+        self.drain_chain(first, |v| values.push(v));
+        Some(values)
+    }
+
+    /// Try once to pop up to `n` values, top first, fewer if the stack runs empty.
+    ///
+    /// Like `pop_n` the run is detached with a single CAS, but if another thread changed
+    /// the top meanwhile nothing is retried and the result is empty, just as for an
+    /// empty stack. Under heavy contention this bounds the time spent, and every CAS
+    /// that goes through takes several values at once.
+    pub fn try_pop_n(&self, n: usize) -> Vec<T> {
+        if Self::is_zst() {
+            let taken = self.take_zst(n);
+            self.stats.popped(taken);
+            return (0..taken).map(|_| unsafe { Self::zst_value() }).collect();
+        }
+        let mut values = Vec::new();
+        if n == 0 {
+            return values;
+        }
+        if let Ok(first) = self.try_pop_chain(&self.top, n, false) {
+            self.drain_chain(first, |v| values.push(v));
+        }
+        values
+    }
+
+    // Detach the first `n` nodes of `list`, or all of them if there are fewer, with a
+    // single CAS. Return the first one, null for an empty list, or fail if another thread got in
+    // the way. If `exact`, detach nothing and return null when there are fewer.
+    fn try_pop_chain(&self,
+                     list: &AtomicStampedPtr<Node<T>>,
+                     n: usize,
+                     exact: bool)
+                     -> Result<*mut Node<T>, ()> {
+        let _walk = self.reclaim.walk();
+        let (p, v) = list.load();
+        if p.is_null() {
+            return Ok(p);
+        }
+        // Nodes below the top may be popped and recycled while we walk, but not
+        // freed, and the CAS fails if anything changed.
+        let mut last = p;
+        for _ in 1..n {
+            let next = unsafe { (*last).next.load(Ordering::Acquire) };
+            if next.is_null() {
+                // The chain was really this short only if the top did not change
+                // while we walked it.
+                if exact {
+                    if list.load() != (p, v) {
+                        return Err(());
                     }
+                    return Ok(core::ptr::null_mut());
+                }
+                break;
+            }
+            last = next;
+        }
+        let rest = unsafe { (*last).next.load(Ordering::Acquire) };
+        list.compare_exchange((p, v), rest).map_err(|_| ())?;
+        unsafe {
+            (*last).next.store(core::ptr::null_mut(), Ordering::Release);
+        }
+        Ok(p)
+    }
+
+    // Take the values of a detached chain and return how many there were.
+    fn drain_chain<F: FnMut(T)>(&self, mut p: *mut Node<T>, mut f: F) -> usize {
+        let n = self.claim_chain(p);
+        while !p.is_null() {
+            let node = p;
+            p = unsafe { (*node).next.load(Ordering::Acquire) };
+            if let Some(v) = self.take_claimed(node) {
+                f(v);
+            }
+        }
+        self.len.fetch_sub(n, Ordering::Release);
+        self.stats.popped(n);
+        n
+    }
+
+    /// Clone the value on the top of stack without removing it, if no available, return None.
+    ///
+    /// The top node is protected by a hazard pointer while its value is cloned, so a
+    /// concurrent pop of that node waits for the clone to finish before moving the value
+    /// out. The returned value was on the top at some instant during the call, but may
+    /// have been popped already by the time the caller sees it.
+    pub fn peek(&self) -> Option<T>
+    where
+        T: Clone + Sync,
+        R: SharedRead,
+    {
+        if Self::is_zst() {
+            if self.is_empty() {
+                return None;
+            }
+            let v = core::mem::ManuallyDrop::new(unsafe { Self::zst_value() });
+            return Some((*v).clone());
+        }
+        let hazard = self.reclaim.acquire();
+        loop {
+            let (p, v) = self.top.load();
+            if p.is_null() {
+                return None;
+            }
+            hazard.protect(p);
+            // The node may have been popped before the hazard was published.
+            if self.top.load() == (p, v) {
+                return unsafe { (*p).data.with(|d| (*d).clone()) };
+            }
+        }
+    }
+
+    /// Clone every value on the stack into a `Vec`, top first, or fail if another thread
+    /// pushed meanwhile.
+    ///
+    /// Unlike `iter_snapshot` the result is exact: the whole chain is detached with one
+    /// CAS, the values are cloned off the detached chain, and the chain is put back with
+    /// another CAS that only succeeds if the top is still as the detach left it. If a
+    /// push got in between, the values pushed meanwhile are taken off too and put back
+    /// above the chain, and `Err(())` tells the caller it can retry. Either way no value
+    /// is lost and the order is kept, but other threads see the stack empty while the
+    /// values are cloned, and while they are put back after a push.
+    #[allow(clippy::result_unit_err)]
+    pub fn try_into_vec(&self) -> Result<Vec<T>, ()>
+    where
+        T: Clone + Sync,
+    {
+        if Self::is_zst() {
+            let v = core::mem::ManuallyDrop::new(unsafe { Self::zst_value() });
+            return Ok((0..self.len()).map(|_| (*v).clone()).collect());
+        }
+        // Puts the chain back if a clone panics.
+        struct Detached<'a, T: 'a, R: Reclaim + 'a, A: NodeAlloc + 'a> {
+            stack: &'a ConcurrentStack<T, R, A>,
+            first: *mut Node<T>,
+            empty: (*mut Node<T>, Stamp),
+        }
+
+        impl<'a, T, R: Reclaim, A: NodeAlloc> Drop for Detached<'a, T, R, A> {
+            fn drop(&mut self) {
+                self.stack.put_back(self.first, self.empty);
+            }
+        }
+
+        let (first, empty) = self.detach_top();
+        if first.is_null() {
+            return Ok(Vec::new());
+        }
+        let detached = Detached { stack: self, first, empty };
+        // The chain is ours now. Traversals that were already walking it may still read
+        // the values, but nobody moves them out or frees the nodes.
+        let mut values = Vec::new();
+        for p in Self::chain(first) {
+            if let Some(v) = unsafe { (*p).data.with(|d| (*d).clone()) } {
+                values.push(v);
+            }
+        }
+        core::mem::forget(detached);
+        if self.put_back(first, empty) {
+            Ok(values)
+        } else {
+            Err(())
+        }
+    }
+
+    // Detach the whole list from the top with one CAS. Return its first node, null if
+    // the stack is empty, and the top the CAS left behind.
+    fn detach_top(&self) -> (*mut Node<T>, (*mut Node<T>, Stamp)) {
+        let mut backoff = self.backoff();
+        loop {
+            let (p, v) = self.top.load();
+            if p.is_null() {
+                return (p, (p, v));
+            }
+            // The CAS bumps the stamp, the top stays at that until another thread
+            // changes it.
+            if self.top.compare_exchange((p, v), core::ptr::null_mut()).is_ok() {
+                return (p, (core::ptr::null_mut(), v.wrapping_add(1)));
+            }
+            self.stats.retried();
+            backoff.snooze();
+        }
+    }
+
+    // Put the chain from `first`, detached by `detach_top` leaving `empty` on the top,
+    // back where it was. If the top changed since, the values pushed meanwhile are
+    // detached too and linked above the chain, until the CAS putting it all back finds
+    // the top as the last detach left it. Return whether the top was unchanged.
+    fn put_back(&self, mut first: *mut Node<T>, mut empty: (*mut Node<T>, Stamp)) -> bool {
+        let mut unchanged = true;
+        while self.top.compare_exchange(empty, first).is_err() {
+            unchanged = false;
+            let (newer, top) = self.detach_top();
+            if !newer.is_null() {
+                // Pushed onto the empty top, so the last of them ends the list. The nodes
+                // are ours again, other threads may only be reading them.
+                let last = Self::chain(newer).last().unwrap();
+                unsafe {
+                    (*last).next.store(first, Ordering::Release);
+                }
+                first = newer;
+            }
+            empty = top;
+        }
+        unchanged
+    }
+
+    /// Clone every value on the stack into a `Vec`, top first, without removing any.
+    ///
+    /// Each node is protected by a hazard pointer while its value is cloned, and no node
+    /// is freed during the walk, so this is safe while other threads push and pop. The
+    /// result is only a best-effort view though: values pushed or popped concurrently
+    /// may be missing or show up twice, and the walk may not even end while other
+    /// threads keep moving nodes around. With exclusive access it is exact.
+    pub fn iter_snapshot(&self) -> Vec<T>
+    where
+        T: Clone + Sync,
+        R: SharedRead,
+    {
+        let mut values = Vec::new();
+        self.visit(|v| {
+            values.push(v.clone());
+            true
+        });
+        values
+    }
+
+    /// Check if any value on the stack equals `value`.
+    ///
+    /// This walks the list like `iter_snapshot`, comparing instead of cloning, so it
+    /// costs O(n) and under concurrent pushes and pops only tells whether the value was
+    /// seen during the walk. It may already be popped when this returns true, or pushed
+    /// just after this returns false.
+    pub fn contains(&self, value: &T) -> bool
+    where
+        T: PartialEq + Sync,
+        R: SharedRead,
+    {
+        let mut found = false;
+        self.visit(|v| {
+            found = v == value;
+            !found
+        });
+        found
+    }
+
+    // Call `f` with every value reachable from the top, top first, until it returns false.
+    fn visit<F: FnMut(&T) -> bool>(&self, mut f: F)
+    where
+        R: SharedRead,
+    {
+        if Self::is_zst() {
+            let v = core::mem::ManuallyDrop::new(unsafe { Self::zst_value() });
+            for _ in 0..self.len() {
+                if !f(&v) {
+                    return;
+                }
+            }
+            return;
+        }
+        let _walk = self.reclaim.walk();
+        let hazard = self.reclaim.acquire();
+        let mut p = self.top.load().0;
+        while !p.is_null() {
+            hazard.protect(p);
+            // A node already claimed by a popper, or sitting in the trash, is skipped,
+            // otherwise its popper waits for the hazard to clear before taking the value.
+            if unsafe { (*p).live.load(Ordering::SeqCst) } {
+                let go_on = unsafe { (*p).data.with(|d| (*d).as_ref().is_none_or(&mut f)) };
+                if !go_on {
+                    return;
+                }
+            }
+            p = unsafe { (*p).next.load(Ordering::Acquire) };
+        }
+    }
+
+    /// Read the operation counters of this stack.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> StackStats {
+        self.stats.snapshot(self.len(), self.trash_len())
+    }
+
+    /// Maximum number of values `try_push` fills the stack with, `usize::MAX` unless set
+    /// with `with_capacity`.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of popped nodes currently kept for reuse.
+    ///
+    /// Like `len` this is a counter maintained next to the list, so it is only a
+    /// snapshot under concurrent pushes and pops.
+    pub fn trash_len(&self) -> usize {
+        Self::clamp_len(self.trash_len.load(Ordering::Relaxed))
+    }
+
+    /// Number of popped nodes kept for reuse at most, see `with_trash_limit`.
+    pub fn trash_limit(&self) -> usize {
+        self.trash_limit
+    }
+
+    /// Allocate `additional` empty nodes for reuse, so that the next `additional` pushes
+    /// don't allocate.
+    ///
+    /// Like `with_preallocated` but for a stack already in use. The trash limit is left
+    /// as is, so nodes beyond it are freed again as values get popped.
+    pub fn reserve(&self, additional: usize) {
+        self.fill_trash(additional);
+    }
+
+    /// Allocate just enough empty nodes for reuse to bring the trash up to `target`,
+    /// and nothing if it already holds that many.
+    ///
+    /// Unlike `reserve` this can be called over and over with the same target without
+    /// growing the pool each time. Concurrent calls share the shortfall between them
+    /// rather than each allocating all of it, but nodes pushes take or pops add
+    /// meanwhile still move the trash away from the target.
+    pub fn reserve_to(&self, target: usize) {
+        if !RECYCLE || Self::is_zst() {
+            return;
+        }
+        // Claim the shortfall on the counter first, so that calls racing with this one
+        // see the trash as full already.
+        let mut have = self.trash_len.load(Ordering::Relaxed);
+        while have < target {
+            match self.trash_len.compare_exchange_weak(have,
+                                                       target,
+                                                       Ordering::Relaxed,
+                                                       Ordering::Relaxed) {
+                Ok(_) => break,
+                Err(now) => have = now,
+            }
+        }
+        for _ in have..target {
+            self.push_top(&self.trash, self.alloc_node(), None);
+            self.stats.allocated();
+        }
+    }
+
+    /// Free every node kept for reuse and return how many were freed.
+    ///
+    /// Only the trash list is touched, so this is safe to call while other threads push
+    /// and pop, later pushes just allocate new nodes. A node another thread is still
+    /// looking at is kept.
+    pub fn shrink(&self) -> usize {
+        let mut detached = 0;
+        let mut freed = 0;
+        for list in [&*self.trash, &self.recent] {
+            let (d, f) = self.shrink_list(list);
+            detached += d;
+            freed += f;
+        }
+        self.trash_len.fetch_sub(detached, Ordering::Relaxed);
+        freed
+    }
+
+    /// Free nodes kept for reuse until at most `keep` are left, and return how many were
+    /// freed.
+    ///
+    /// Unlike `shrink`, nodes are taken off the trash one at a time, so pushes meanwhile
+    /// still find the others. A node another thread is still looking at is put back and
+    /// ends the call.
+    pub fn shrink_to(&self, keep: usize) -> usize {
+        let mut freed = 0;
+        while self.trash_len.load(Ordering::Relaxed) > keep {
+            let mut node = self.pop_top(&self.trash, None);
+            if node.is_null() && self.fifo_trash {
+                node = self.refill_trash();
+            }
+            if node.is_null() {
+                break;
+            }
+            if !self.retire(node) {
+                if !self.reclaim.may_free(node) {
+                    self.push_top(&self.trash, node, None);
+                    break;
+                }
+                unsafe {
+                    self.free_node(node);
+                }
+            }
+            self.trash_len.fetch_sub(1, Ordering::Relaxed);
+            freed += 1;
+        }
+        freed
+    }
+
+    // Free the nodes of a trash list, return how many were detached and how many freed.
+    fn shrink_list(&self, list: &AtomicStampedPtr<Node<T>>) -> (usize, usize) {
+        let mut p = list.swap(core::ptr::null_mut());
+        let mut detached = 0;
+        let mut freed = 0;
+        while !p.is_null() {
+            let node = p;
+            p = unsafe { (*node).next.load(Ordering::Acquire) };
+            detached += 1;
+            if self.retire(node) {
+                freed += 1;
+            } else if self.reclaim.may_free(node) {
+                unsafe {
+                    self.free_node(node);
+                }
+                freed += 1;
+            } else {
+                self.trash_len.fetch_add(1, Ordering::Relaxed);
+                self.push_top(&self.trash, node, None);
+            }
+        }
+        (detached, freed)
+    }
+
+    /// Size and alignment of the node holding each value, for sizing the slots of an
+    /// external pool.
+    ///
+    /// Nodes are `repr(C)`, so this only depends on `T`. Values of a zero-sized type
+    /// are stored without a node, the layout is reported for them all the same.
+    pub const fn node_layout() -> Layout {
+        Layout::new::<Node<T>>()
+    }
+
+    /// Load the top node of the stack and the stamp of the top, both raw.
+    ///
+    /// The top is null for an empty stack, and always for a zero-sized `T`. The stamp
+    /// changes with every push and pop, so two equal loads mean the stack did not change
+    /// in between, unless the stamp wrapped around.
+    ///
+    /// # Safety
+    ///
+    /// Nothing protects the node: it may be popped, recycled for another value or freed
+    /// by the time this returns. It must not be dereferenced, only compared, and an
+    /// equal address alone does not mean it still holds the same value.
+    ///
+    /// # Examples
+    ///
+    /// Peek optimistically, and only trust the value if the stack did not change while
+    /// it was read. `peek` takes care of protecting the node.
+    ///
+    /// ```no_run
+    /// use concurrent_stack::ConcurrentStack;
+    ///
+    /// let stack = ConcurrentStack::new();
+    /// stack.push(1);
+    /// let top = loop {
+    ///     let before = unsafe { stack.current_top() };
+    ///     let v = stack.peek();
+    ///     if unsafe { stack.current_top() } == before {
+    ///         break v;
+    ///     }
+    /// };
+    /// assert_eq!(top, Some(1));
+    /// ```
+    pub unsafe fn current_top(&self) -> (*const Node<T>, usize) {
+        let (p, v) = self.top.load();
+        // Only the fallback stamp is a `u64`, truncated on 32 bit targets.
+        #[allow(clippy::unnecessary_cast)]
+        (p as *const Node<T>, v as usize)
+    }
+
+    /// Count the values on the stack by walking the list.
+    ///
+    /// This is O(n) and meant for debugging, `len` is the cheap way. No node is freed
+    /// while the walk runs, so it is memory safe, but under concurrent pushes and pops
+    /// the count is meaningless and the walk may not even end while other threads keep
+    /// moving nodes around. Only rely on it when no other thread touches the stack.
+    pub fn len_slow(&self) -> usize {
+        if Self::is_zst() {
+            return self.len();
+        }
+        let _walk = self.reclaim.walk();
+        let mut p = self.top.load().0;
+        let mut n = 0;
+        while !p.is_null() {
+            p = unsafe { (*p).next.load(Ordering::Acquire) };
+            n += 1;
+        }
+        n
+    }
+
+    /// Number of values on the stack.
+    ///
+    /// This is only a snapshot: other threads may push or pop at any moment, so the
+    /// value can be stale by the time the caller reads it.
+    pub fn len(&self) -> usize {
+        Self::clamp_len(self.len.load(Ordering::Acquire))
+    }
+
+    /// Number of values on the stack, read as cheaply as possible.
+    ///
+    /// The counter is loaded with `Relaxed`, so the result is not ordered with anything
+    /// else this thread observed and may be arbitrarily stale, though never more than
+    /// the number of values pushed so far. Meant for sampling at a high rate, like
+    /// monitoring, use `len` when the answer must be consistent with other reads.
+    pub fn len_hint(&self) -> usize {
+        Self::clamp_len(self.len.load(Ordering::Relaxed))
+    }
+
+    /// Check if stack is empty.
+    ///
+    /// The answer is linearizable: it is exact for the instant `top` is loaded, which
+    /// is ordered like a SeqCst operation with every push and pop. So a thread always
+    /// sees its own earlier pushes, and the stack is only reported empty if at that
+    /// instant every value pushed before had been popped. It may of course have been
+    /// pushed to or drained by another thread by the time the caller looks at it.
+    pub fn is_empty(&self) -> bool {
+        if Self::is_zst() {
+            return self.len() == 0;
+        }
+        self.top.load().0.is_null()
+    }
+
+    /// Check if stack is empty.
+    #[deprecated(note = "use is_empty instead")]
+    pub fn empty(&self) -> bool {
+        self.is_empty()
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAlloc> Drop for ConcurrentStack<T, R, A> {
+    fn drop(&mut self) {
+        if Self::is_zst() {
+            self.clear();
+        }
+        if cfg!(debug_assertions) {
+            Self::assert_acyclic(self.top.load().0);
+            Self::assert_acyclic(self.trash.load().0);
+            Self::assert_acyclic(self.recent.load().0);
+        }
+        self.release();
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAlloc + Default> Default for ConcurrentStack<T, R, A> {
+    fn default() -> Self {
+        Self::with_parts(A::default())
+    }
+}
+
+/// Shows the length and emptiness of the stack, not its values. Both are snapshots
+/// taken one after the other, so they may disagree under concurrency.
+impl<T, R: Reclaim, A: NodeAlloc> core::fmt::Debug for ConcurrentStack<T, R, A> {
+    fn fmt(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.debug_struct("ConcurrentStack")
+         .field("len", &self.len())
+         .field("empty", &self.is_empty())
+         .finish()
+    }
+}
+
+/// Creates a stack with the same values and settings.
+///
+/// The values are cloned while walking the list, so under concurrent pushes and pops
+/// the copy is a best-effort snapshot that may miss values or hold some twice. With
+/// exclusive access it is exact.
+impl<T: Clone + Sync, R: SharedRead, A: NodeAlloc + Clone> Clone for ConcurrentStack<T, R, A> {
+    fn clone(&self) -> Self {
+        let stack = self.empty_like();
+        stack.push_iter(self.iter_snapshot().into_iter().rev());
+        stack
+    }
+}
+
+/// Compares the values of both stacks from top to bottom.
+///
+/// Both stacks are snapshotted with `iter_snapshot` one after the other, so this is
+/// only meaningful when no other thread changes them meanwhile, under concurrency it is
+/// a best-effort answer.
+impl<T: PartialEq + Clone + Sync, R: SharedRead, A: NodeAlloc> PartialEq for ConcurrentStack<T, R, A> {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self, other) || self.iter_snapshot() == other.iter_snapshot()
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAlloc + Default> core::iter::FromIterator<T>
+    for ConcurrentStack<T, R, A> {
+    /// Push values in iteration order, so the last one ends up on the top.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let stack = Self::with_parts(A::default());
+        for v in iter {
+            stack.push(v);
+        }
+        stack
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAlloc> Extend<T> for ConcurrentStack<T, R, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for v in iter {
+            self.push(v);
+        }
+    }
+}
+
+/// Extends a shared stack, for instance through an `Arc`, with `push_iter`, so each
+/// call puts all its values on the top at once.
+impl<T, R: Reclaim, A: NodeAlloc> Extend<T> for &ConcurrentStack<T, R, A> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.push_iter(iter);
+    }
+}
+
+/// The outcome of `ConcurrentStack::pop_or_closed`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PopResult<T> {
+    /// The value popped from the top.
+    Value(T),
+    /// The stack is empty for now, but more values may be pushed.
+    Empty,
+    /// The stack is closed and empty, no value is pushed any more.
+    Closed,
+}
+
+/// An iterator that moves values out of a stack, from top to bottom.
+pub struct IntoIter<T, R: Reclaim = HazardReclaim, A: NodeAlloc = Global> {
+    stack: ConcurrentStack<T, R, A>,
+}
+
+impl<T, R: Reclaim, A: NodeAlloc> Iterator for IntoIter<T, R, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+}
+
+impl<T, R: Reclaim, A: NodeAlloc> IntoIterator for ConcurrentStack<T, R, A> {
+    type Item = T;
+    type IntoIter = IntoIter<T, R, A>;
+
+    fn into_iter(self) -> IntoIter<T, R, A> {
+        IntoIter { stack: self }
+    }
+}
+
+/// An iterator that pops values off a shared stack until it finds it empty.
+///
+/// Every `next` is a `pop`, so iterating drains the stack as a side effect. Other
+/// threads may keep pushing and popping meanwhile: the iterator yields whatever it
+/// manages to pop, and ends at the first pop that finds the stack empty, even if more
+/// values are pushed right after.
+pub struct PopIter<'a, T: 'a, R: Reclaim + 'a = HazardReclaim, A: NodeAlloc + 'a = Global> {
+    stack: &'a ConcurrentStack<T, R, A>,
+}
+
+impl<'a, T, R: Reclaim, A: NodeAlloc> Iterator for PopIter<'a, T, R, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+}
+
+impl<'a, T, R: Reclaim, A: NodeAlloc> IntoIterator for &'a ConcurrentStack<T, R, A> {
+    type Item = T;
+    type IntoIter = PopIter<'a, T, R, A>;
+
+    fn into_iter(self) -> PopIter<'a, T, R, A> {
+        PopIter { stack: self }
+    }
+}
+
+/// Pops values off a stack lazily and clears it when dropped, see
+/// `ConcurrentStack::drain`.
+pub struct DrainGuard<'a, T: 'a, R: Reclaim + 'a = HazardReclaim, A: NodeAlloc + 'a = Global> {
+    stack: &'a ConcurrentStack<T, R, A>,
+}
+
+impl<'a, T, R: Reclaim, A: NodeAlloc> Iterator for DrainGuard<'a, T, R, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.stack.pop()
+    }
+}
+
+impl<'a, T, R: Reclaim, A: NodeAlloc> Drop for DrainGuard<'a, T, R, A> {
+    fn drop(&mut self) {
+        self.stack.clear();
+    }
+}
+
+/// The nodes of a stack detached by `ConcurrentStack::take_chain`, yielding their
+/// values from top to bottom.
+///
+/// Each node is freed as its value is moved out, and those left are freed with their
+/// values when the chain is dropped.
+pub struct StackChain<T, A: NodeAlloc = Global> {
+    head: *mut Node<T>,
+    // Values left, which is all there is to a chain of zero-sized values.
+    len: usize,
+    alloc: A,
+    _values: PhantomData<T>,
+}
+
+// No other thread can reach the nodes any more.
+unsafe impl<T: Send, A: NodeAlloc> Send for StackChain<T, A> {}
+unsafe impl<T: Sync, A: NodeAlloc> Sync for StackChain<T, A> {}
+
+impl<T, A: NodeAlloc> Iterator for StackChain<T, A> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        if core::mem::size_of::<T>() == 0 {
+            // Conjured like `ConcurrentStack::zst_value`, the pushed ones were forgotten.
+            return Some(unsafe { core::ptr::NonNull::<T>::dangling().as_ptr().read() });
+        }
+        let node = unsafe { core::ptr::read(self.head) };
+        unsafe {
+            self.alloc.dealloc(self.head as *mut u8, Layout::new::<Node<T>>());
+        }
+        self.head = node.next.load(Ordering::Relaxed);
+        node.data.into_inner()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.len, Some(self.len))
+    }
+}
+
+impl<T, A: NodeAlloc> ExactSizeIterator for StackChain<T, A> {}
+
+impl<T, A: NodeAlloc> Drop for StackChain<T, A> {
+    fn drop(&mut self) {
+        for _ in self {}
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::{ConcurrentStack, EpochReclaim, HazardReclaim, Reclaim, SharedRead};
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    // Counts heap allocations and frees per thread, so tests running in parallel don't
+    // see each other's.
+    struct CountingAlloc;
+
+    thread_local! {
+        static ALLOCS: Cell<usize> = const { Cell::new(0) };
+        static FREES: Cell<usize> = const { Cell::new(0) };
+        static OUT_OF_MEMORY: Cell<bool> = const { Cell::new(false) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            if OUT_OF_MEMORY.try_with(|oom| oom.get()).unwrap_or(false) {
+                return std::ptr::null_mut();
+            }
+            let _ = ALLOCS.try_with(|n| n.set(n.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let _ = FREES.try_with(|n| n.set(n.get() + 1));
+            System.dealloc(ptr, layout)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+    fn allocs() -> usize {
+        ALLOCS.with(|n| n.get())
+    }
+
+    fn frees() -> usize {
+        FREES.with(|n| n.get())
+    }
+
+    // Make every allocation of this thread fail while `f` runs.
+    fn out_of_memory<R, F: FnOnce() -> R>(f: F) -> R {
+        OUT_OF_MEMORY.with(|oom| oom.set(true));
+        let r = f();
+        OUT_OF_MEMORY.with(|oom| oom.set(false));
+        r
+    }
+
+    #[test]
+    fn lock_free_stack_single_thread() {
+        let stack = ConcurrentStack::new();
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.pop(), Some(2));
+        stack.push(4);
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.pop(), Some(1));
+    }
+
+    #[test]
+    fn multi_thread_sum() {
+        let stack = Arc::new(ConcurrentStack::new());
+
+        let input_p = (0..10)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..100 {
+                                      stack.push(i);
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+
+
+        let mut sum = 0;
+
+        let output_p = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                loop {
+                    if let Some(i) = stack.pop() {
+                        sum += i;
+                    } else if sum == 49500 {
+                        break;
+                    }
+                }
+            })
+        };
+
+        for t in input_p {
+            t.join().unwrap();
+        }
+        output_p.join().unwrap();
+
+        assert!(stack.is_empty());
+    }
+
+    fn churn_with<R: Reclaim + 'static>(stack: ConcurrentStack<usize, R>) {
+        let stack = Arc::new(stack);
+        let workers = (0..8)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut popped = 0;
+                                  for i in 0..1000 {
+                                      stack.push(t * 1000 + i);
+                                      if let Some(v) = stack.pop() {
+                                          popped += v;
+                                      }
+                                  }
+                                  popped
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let popped: usize = workers.into_iter().map(|t| t.join().unwrap()).sum();
+        let left: usize = stack.pop_all().into_iter().sum();
+        assert_eq!(popped + left, (0..8000).sum::<usize>());
+    }
+
+    // Runs the same scenarios on a stack of every backend.
+    fn reclaim_scenarios<R: Reclaim + 'static>() {
+        let stack = ConcurrentStack::<i32, R>::with_reclaim();
+        assert_eq!(stack.pop(), None);
+        stack.push_iter(0..5);
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.pop_n(2), vec![3, 2]);
+        assert_eq!(stack.pop_all(), vec![1, 0]);
+        assert!(stack.is_empty());
+
+        churn_with(ConcurrentStack::<usize, R>::with_reclaim());
+
+        // Chains are taken and handed over while other threads pop from them.
+        let stack = Arc::new(ConcurrentStack::<String, R>::with_reclaim());
+        let other = ConcurrentStack::<String, R>::with_reclaim();
+        let poppers = (0..2)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..2000 {
+                                      stack.push(i.to_string());
+                                      stack.push_iter((0..3).map(|j| j.to_string()));
+                                      stack.pop();
+                                      stack.pop_n(2);
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        for i in 0..500 {
+            match i % 3 {
+                0 => assert!(stack.take_chain().all(|v| v.parse::<i32>().is_ok())),
+                1 => other.append(&stack),
+                _ => other.append(&stack.split_off(1)),
+            }
+        }
+        for t in poppers {
+            t.join().unwrap();
+        }
+        assert!(other.pop_all().iter().all(|v| v.parse::<i32>().is_ok()));
+    }
+
+    // Like `reclaim_scenarios`, for the methods reading values in place.
+    fn read_scenarios<R: SharedRead + 'static>() {
+        let stack = ConcurrentStack::<i32, R>::with_reclaim();
+        stack.push_iter(0..5);
+        assert_eq!(stack.peek(), Some(4));
+        assert!(stack.contains(&2));
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.pop_n(2), vec![3, 2]);
+        assert_eq!(stack.iter_snapshot(), vec![1, 0]);
+        assert_eq!(stack.pop_all(), vec![1, 0]);
+
+        // Readers clone values while other threads pop and free their nodes.
+        let stack = Arc::new(ConcurrentStack::<String, R>::with_reclaim());
+        let poppers = (0..2)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..2000 {
+                                      stack.push(i.to_string());
+                                      stack.pop();
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        for _ in 0..2000 {
+            if let Some(v) = stack.peek() {
+                assert!(v.parse::<i32>().is_ok());
+            }
+            for v in stack.iter_snapshot() {
+                assert!(v.parse::<i32>().is_ok());
+            }
+        }
+        for t in poppers {
+            t.join().unwrap();
+        }
+        stack.clear();
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn hazard_reclaim() {
+        reclaim_scenarios::<HazardReclaim>();
+        read_scenarios::<HazardReclaim>();
+    }
+
+    #[test]
+    fn epoch_reclaim() {
+        reclaim_scenarios::<EpochReclaim>();
+        read_scenarios::<EpochReclaim>();
+    }
+
+    #[cfg(feature = "crossbeam-epoch")]
+    #[test]
+    fn crossbeam_reclaim() {
+        use super::CrossbeamReclaim;
+
+        reclaim_scenarios::<CrossbeamReclaim>();
+        // Holding a guard of our own neither holds up pops nor the chain operations.
+        let stack = ConcurrentStack::<i32, CrossbeamReclaim>::with_reclaim();
+        let guard = ::crossbeam_epoch::pin();
+        stack.push_iter(0..4);
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.take_chain().collect::<Vec<_>>(), vec![2, 1, 0]);
+        drop(guard);
+        // With the global allocator nodes are not recycled, and those reserved are
+        // handed over on shrink.
+        #[cfg(not(feature = "allocator_api"))]
+        {
+            stack.push(1);
+            stack.pop();
+            assert_eq!(stack.trash_len(), 0);
+            stack.reserve(4);
+            assert_eq!(stack.shrink(), 4);
+            assert_eq!(stack.trash_len(), 0);
+        }
+    }
+
+    #[test]
+    fn spin_policy() {
+        use super::{RetryPolicy, SpinPolicy};
+
+        churn_with(ConcurrentStack::with_policy(SpinPolicy::default()));
+        churn_with(ConcurrentStack::with_policy(SpinPolicy::new(0)));
+        // The limit is capped, a large one does not overflow the spin count.
+        SpinPolicy::new(u32::MAX).retry(100);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn yield_and_park_policies() {
+        use super::{ParkPolicy, YieldPolicy};
+        use std::time::Duration;
+
+        churn_with(ConcurrentStack::with_policy(YieldPolicy));
+        churn_with(ConcurrentStack::with_policy(ParkPolicy::default()));
+        churn_with(ConcurrentStack::with_policy(ParkPolicy::new(0, Duration::from_micros(1))));
+    }
+
+    #[test]
+    fn boxed_closures_run_once() {
+        let mut runs = vec![0; 4];
+        {
+            let stack: ConcurrentStack<Box<dyn FnOnce() + '_>> = ConcurrentStack::new();
+            for (i, run) in runs.iter_mut().enumerate() {
+                stack.push(Box::new(move || *run += i + 1));
+            }
+            assert_eq!(stack.len(), 4);
+            while let Some(task) = stack.pop() {
+                task();
+            }
+        }
+        assert_eq!(runs, [1, 2, 3, 4]);
+
+        let tasks = Arc::new(ConcurrentStack::<Box<dyn FnOnce() -> usize + Send>>::new());
+        let pushers = (0..4)
+                          .map(|t| {
+                              let tasks = tasks.clone();
+                              thread::spawn(move || {
+                                  for i in 0..100 {
+                                      tasks.push(Box::new(move || t * 100 + i));
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        for p in pushers {
+            p.join().unwrap();
+        }
+        let mut out = tasks.pop_all().into_iter().map(|task| task()).collect::<Vec<_>>();
+        out.sort();
+        assert_eq!(out, (0..400).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn store_uncopyable() {
+        let stack = ConcurrentStack::new();
+        stack.push(Box::new(1));
+        stack.push(Box::new(2));
+        stack.push(Box::new(3));
+        assert_eq!(*stack.pop().unwrap(), 3);
+        assert_eq!(*stack.pop().unwrap(), 2);
+        assert_eq!(*stack.pop().unwrap(), 1);
+    }
+
+    #[test]
+    fn pop_or_insert_with_pools() {
+        let pool = ConcurrentStack::new();
+        let made = Cell::new(0);
+        let get = || {
+            pool.pop_or_insert_with(|| {
+                     made.set(made.get() + 1);
+                     vec![0u8; 16]
+                 })
+        };
+        let a = get();
+        let b = get();
+        assert_eq!(made.get(), 2);
+        assert!(pool.is_empty());
+        pool.push(a);
+        pool.push(b);
+        get();
+        assert_eq!(made.get(), 2);
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn pop_if_only_matching() {
+        let stack: ConcurrentStack<i32> = vec![1, 3, 2, 4].into_iter().collect();
+        assert_eq!(stack.pop_if(|v| v % 2 == 0), Some(4));
+        assert_eq!(stack.pop_if(|v| v % 2 == 0), Some(2));
+        assert_eq!(stack.pop_if(|v| v % 2 == 0), None);
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.pop_all(), vec![3, 1]);
+        assert_eq!(stack.pop_if(|_| true), None);
+    }
+
+    #[test]
+    fn swap_top_replaces_or_pushes() {
+        let stack = ConcurrentStack::new();
+        assert_eq!(stack.swap_top(String::from("a")), None);
+        assert_eq!(stack.len(), 1);
+        stack.push(String::from("b"));
+        assert_eq!(stack.swap_top(String::from("c")).as_deref(), Some("b"));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.pop_all(), vec!["c", "a"]);
+
+        let units = ConcurrentStack::new();
+        assert_eq!(units.swap_top(()), None);
+        assert_eq!(units.swap_top(()), Some(()));
+        assert_eq!(units.len(), 1);
+    }
+
+    #[test]
+    fn push_if_empty_only_once() {
+        let stack = ConcurrentStack::new();
+        assert_eq!(stack.push_if_empty(1), Ok(()));
+        assert_eq!(stack.push_if_empty(2), Err(2));
+        assert_eq!(stack.pop(), Some(1));
+        assert_eq!(stack.push_if_empty(3), Ok(()));
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.pop_all(), vec![3]);
+
+        for _ in 0..100 {
+            let stack = Arc::new(ConcurrentStack::new());
+            let threads = (0..2)
+                              .map(|i| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || stack.push_if_empty(i).is_ok())
+                              })
+                              .collect::<Vec<_>>();
+            let won = threads.into_iter().map(|t| t.join().unwrap()).filter(|&ok| ok).count();
+            assert_eq!(won, 1);
+            assert_eq!(stack.len(), 1);
+        }
+
+        let units = ConcurrentStack::new();
+        assert_eq!(units.push_if_empty(()), Ok(()));
+        assert_eq!(units.push_if_empty(()), Err(()));
+    }
+
+    #[test]
+    fn push_dedup_skips_equal_top() {
+        let stack = ConcurrentStack::new();
+        assert!(stack.push_dedup(1));
+        assert!(!stack.push_dedup(1));
+        assert!(stack.push_dedup(2));
+        // Only the top counts.
+        assert!(stack.push_dedup(1));
+        assert_eq!(stack.pop_all(), vec![1, 2, 1]);
+
+        let units = ConcurrentStack::new();
+        assert!(units.push_dedup(()));
+        assert!(!units.push_dedup(()));
+        assert_eq!(units.len(), 1);
+    }
+
+    #[test]
+    fn push_dedup_contended() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..4)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || (0..1000).filter(|&i| stack.push_dedup(i / 10)).count())
+                          })
+                          .collect::<Vec<_>>();
+        let pushed: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        let values = stack.pop_all();
+        assert_eq!(values.len(), pushed);
+        assert!(values.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn swap_top_contended() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..4)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut got = Vec::new();
+                                  for i in 0..1000 {
+                                      match i % 3 {
+                                          0 => stack.push(t * 1000 + i),
+                                          1 => got.extend(stack.swap_top(t * 1000 + i)),
+                                          _ => got.extend(stack.pop()),
+                                      }
+                                  }
+                                  got
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let mut all = Vec::new();
+        for t in threads {
+            all.extend(t.join().unwrap());
+        }
+        all.extend(stack.pop_all());
+        all.sort();
+        let pushed = (0..4).flat_map(|t| (0..1000).filter(|i| i % 3 != 2).map(move |i| t * 1000 + i));
+        assert_eq!(all, pushed.collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pop_while_takes_matching_run() {
+        let stack: ConcurrentStack<i32> = vec![1, 3, 2, 4].into_iter().collect();
+        assert_eq!(stack.pop_while(|v| *v > 4), Vec::<i32>::new());
+        assert_eq!(stack.len(), 4);
+        assert_eq!(stack.pop_while(|v| v % 2 == 0), vec![4, 2]);
+        assert_eq!(stack.pop_all(), vec![3, 1]);
+
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        assert_eq!(stack.pop_while(|_| true), vec![4, 3, 2, 1, 0]);
+        assert!(stack.is_empty());
+    }
+
+    // Every thread pushes unique values and pops one after each, on a stack keeping at
+    // most 4 nodes for reuse, so a handful of addresses go through the heads over and
+    // over. Every value must come out exactly once.
+    fn recycle_small_pool(rounds: usize) {
+        const THREADS: usize = 8;
+        let stack = Arc::new(ConcurrentStack::with_trash_limit(4));
+        let seen = (0..THREADS * rounds).map(|_| AtomicBool::new(false)).collect::<Vec<_>>();
+        let seen = Arc::new(seen);
+        let mark = |seen: &[AtomicBool], v: usize| {
+            assert!(!seen[v].swap(true, Ordering::Relaxed), "{} popped twice", v);
+        };
+        let threads = (0..THREADS)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              let seen = seen.clone();
+                              thread::spawn(move || {
+                                  for i in 0..rounds {
+                                      stack.push(t * rounds + i);
+                                      if let Some(v) = stack.pop() {
+                                          mark(&seen, v);
+                                      }
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        for v in stack.pop_all() {
+            mark(&seen, v);
+        }
+        assert!(seen.iter().all(|s| s.load(Ordering::Relaxed)));
+    }
+
+    #[test]
+    fn recycle_small_pool_short() {
+        recycle_small_pool(20_000);
+    }
+
+    // The same for millions of rounds. Run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn recycle_small_pool_long() {
+        recycle_small_pool(2_000_000);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn pop_yield_oversubscribed() {
+        use std::time::Instant;
+
+        // Far more threads than cores, each pushing and then popping its share.
+        fn run(pop: fn(&ConcurrentStack<usize>) -> Option<usize>) -> std::time::Duration {
+            let threads = 16 * thread::available_parallelism().map_or(4, |n| n.get());
+            let stack = Arc::new(ConcurrentStack::new());
+            let start = Instant::now();
+            let handles = (0..threads)
+                              .map(|_| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || {
+                                      for i in 0..200 {
+                                          stack.push(i);
+                                      }
+                                      (0..200).filter(|_| pop(&stack).is_some()).count()
+                                  })
+                              })
+                              .collect::<Vec<_>>();
+            let popped: usize = handles.into_iter().map(|t| t.join().unwrap()).sum();
+            assert_eq!(popped, threads * 200);
+            assert!(stack.is_empty());
+            start.elapsed()
+        }
+
+        let spinning = run(ConcurrentStack::pop);
+        let yielding = run(ConcurrentStack::pop_yield);
+        assert!(yielding < spinning * 10 + std::time::Duration::from_secs(1),
+                "pop_yield took {:?}, pop {:?}",
+                yielding,
+                spinning);
+    }
+
+    #[test]
+    fn try_pop_single_attempt() {
+        let stack = ConcurrentStack::new();
+        assert_eq!(stack.try_pop(), Ok(None));
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.try_pop(), Ok(Some(2)));
+        assert_eq!(stack.try_pop(), Ok(Some(1)));
+        assert_eq!(stack.try_pop(), Ok(None));
+    }
+
+    #[test]
+    fn try_pop_contended() {
+        let stack = Arc::new((0..40000).collect::<ConcurrentStack<i32>>());
+        let threads = (0..4)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut popped = 0;
+                                  loop {
+                                      match stack.try_pop() {
+                                          Ok(Some(_)) => popped += 1,
+                                          Ok(None) => return popped,
+                                          Err(()) => thread::yield_now(),
+                                      }
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+
+        let popped: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(popped, 40000);
+    }
+
+    #[test]
+    fn target_matrix() {
+        assert_eq!(super::IS_LOCK_FREE, cfg!(all(target_arch = "x86_64", feature = "std")));
+    }
+
+    #[test]
+    fn portable_stamped_ptr() {
+        use super::stamped::AtomicStampedPtr;
+
+        let mut x = 1;
+        let mut y = 2;
+        let (px, py) = (&mut x as *mut i32, &mut y as *mut i32);
+        let a = AtomicStampedPtr::default();
+        assert_eq!(a.load(), (std::ptr::null_mut(), 0));
+        assert_eq!(a.swap(px), std::ptr::null_mut());
+        assert_eq!(a.compare_exchange((px, 0), py), Err((px, 1)));
+        assert_eq!(a.compare_exchange((px, 1), py), Ok((px, 1)));
+        assert_eq!(a.load(), (py, 2));
+
+        let b = Arc::new(AtomicStampedPtr::new(px));
+        let threads = (0..4)
+                          .map(|_| {
+                              let b = b.clone();
+                              thread::spawn(move || {
+                                  for _ in 0..1000 {
+                                      let mut cur = b.load();
+                                      while let Err(c) = b.compare_exchange(cur, cur.0) {
+                                          cur = c;
+                                      }
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(b.load(), (px, 4000));
+    }
+
+    #[test]
+    fn stamp_wraps_around() {
+        use super::stamped::AtomicStampedPtr;
+
+        let mut x = 1;
+        let px = &mut x as *mut i32;
+        let a = AtomicStampedPtr::with_stamp(px, u64::MAX);
+        assert_eq!(a.compare_exchange((px, u64::MAX), px), Ok((px, u64::MAX)));
+        assert_eq!(a.load(), (px, 0));
+        // A stale stamp from before the wrap no longer matches.
+        assert_eq!(a.compare_exchange((px, u64::MAX), px), Err((px, 0)));
+        assert_eq!(a.swap(px), px);
+        assert_eq!(a.load(), (px, 1));
+    }
+
+    #[test]
+    fn wait_walkers_skips_later_walks() {
+        use super::hazard::Hazards;
+
+        let hazards = Hazards::new();
+        let started = AtomicBool::new(false);
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                // Walks overlap, so there is never a moment without one.
+                let mut walk = hazards.walk();
+                started.store(true, Ordering::SeqCst);
+                while !done.load(Ordering::SeqCst) {
+                    let next = hazards.walk();
+                    walk = next;
+                }
+                drop(walk);
+            });
+            while !started.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+            hazards.wait_walkers();
+            done.store(true, Ordering::SeqCst);
+        });
+    }
+
+    // Recycle a single node through both heads billions of times while another thread
+    // keeps validating against stale stamps. Run with `cargo test --release -- --ignored`.
+    #[test]
+    #[ignore]
+    fn churn_single_node() {
+        const ROUNDS: usize = 1 << 32;
+        let stack = Arc::new(ConcurrentStack::with_preallocated(1));
+        let done = Arc::new(AtomicBool::new(false));
+        let watcher = {
+            let stack = stack.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    if let Some(v) = stack.peek() {
+                        assert!(v < ROUNDS);
+                    }
+                    assert!(stack.len_slow() <= 1);
+                }
+            })
+        };
+        for i in 0..ROUNDS {
+            stack.push(i);
+            assert_eq!(stack.pop(), Some(i));
+        }
+        done.store(true, Ordering::Relaxed);
+        watcher.join().unwrap();
+        assert!(stack.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn blocking_pop_wakes_on_push() {
+        use super::BlockingStack;
+        use std::time::Duration;
+
+        let stack = Arc::new(BlockingStack::new());
+        assert_eq!(stack.pop_timeout(Duration::from_millis(10)), None);
+
+        let consumer = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.pop_blocking())
+        };
+        thread::sleep(Duration::from_millis(50));
+        stack.push(42);
+        assert_eq!(consumer.join().unwrap(), 42);
+        assert!(stack.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn async_pop_woken_by_push() {
+        use super::AsyncStack;
+        use std::future::Future;
+        use std::pin::pin;
+        use std::task::{Context, Poll, Wake, Waker};
+
+        struct Flag(AtomicUsize);
+
+        impl Wake for Flag {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let flag = Arc::new(Flag(AtomicUsize::new(0)));
+        let waker = Waker::from(flag.clone());
+        let mut cx = Context::from_waker(&waker);
+        let stack = AsyncStack::new();
+        assert_eq!(stack.poll_pop(&mut cx), Poll::Pending);
+        // Polling again does not register the same waker twice.
+        assert_eq!(stack.poll_pop(&mut cx), Poll::Pending);
+        stack.push(1);
+        assert_eq!(flag.0.load(Ordering::SeqCst), 1);
+        stack.push(2);
+        assert_eq!(flag.0.load(Ordering::SeqCst), 1);
+        assert_eq!(stack.poll_pop(&mut cx), Poll::Ready(2));
+
+        let mut pop = pin!(stack.pop_async());
+        assert_eq!(pop.as_mut().poll(&mut cx), Poll::Ready(1));
+        let mut pop = pin!(stack.pop_async());
+        assert_eq!(pop.as_mut().poll(&mut cx), Poll::Pending);
+        stack.push(3);
+        assert_eq!(flag.0.load(Ordering::SeqCst), 2);
+        assert_eq!(pop.as_mut().poll(&mut cx), Poll::Ready(3));
+        assert!(stack.is_empty());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn bounded_push_waits_for_room() {
+        use super::BoundedBlockingStack;
+        use std::time::Duration;
+
+        let stack = Arc::new(BoundedBlockingStack::new(2));
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.try_push(3), Err(3));
+        let done = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let stack = stack.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                stack.push(3);
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!done.load(Ordering::SeqCst));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.pop(), Some(2));
+        producer.join().unwrap();
+        assert!(done.load(Ordering::SeqCst));
+        assert_eq!(stack.len(), 2);
+        assert_eq!(stack.pop(), Some(3));
+        assert_eq!(stack.pop(), Some(1));
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn recycled_node_has_no_stale_value() {
+        let stack = ConcurrentStack::new();
+        stack.push(String::from("old"));
+        assert_eq!(stack.pop().as_deref(), Some("old"));
+
+        let before = allocs();
+        stack.push(String::from("new"));
+        if super::RECYCLE {
+            // The node of "old" was reused.
+            assert_eq!(allocs(), before + 1);
+        }
+        assert_eq!(stack.pop().as_deref(), Some("new"));
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn clone_is_independent() {
+        let stack: ConcurrentStack<String> = (0..5).map(|i| i.to_string()).collect();
+        let copy = stack.clone();
+        stack.pop();
+        stack.push(String::from("x"));
+        assert_eq!(copy.len(), 5);
+        assert_eq!(copy.pop_all(), vec!["4", "3", "2", "1", "0"]);
+        assert_eq!(stack.pop_all(), vec!["x", "3", "2", "1", "0"]);
+    }
+
+    #[test]
+    fn map_keeps_order() {
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        let mut calls = Vec::new();
+        let strings = stack.map(|v| {
+            calls.push(v);
+            format!("#{}", v)
+        });
+        assert_eq!(calls, vec![4, 3, 2, 1, 0]);
+        assert_eq!(strings.len(), 5);
+        assert_eq!(strings.pop_all(), vec!["#4", "#3", "#2", "#1", "#0"]);
+    }
+
+    #[test]
+    fn map_reuses_nodes() {
+        let stack: ConcurrentStack<u64> = (0..100).collect();
+        let allocated = allocs();
+        let doubled = stack.map(|v| v as i64 * 2);
+        // No node, only the cell of the new top where the list heads have one.
+        assert_eq!(allocs() - allocated, super::IS_LOCK_FREE as usize);
+        doubled.push(-1);
+        assert_eq!(doubled.len(), 101);
+        assert_eq!(doubled.pop(), Some(-1));
+        assert_eq!(doubled.pop_all(), (0..100).rev().map(|v| v * 2).collect::<Vec<_>>());
+        let units = (0..3).collect::<ConcurrentStack<i32>>().map(|_| ());
+        assert_eq!(units.len(), 3);
+    }
+
+    #[test]
+    fn eq_compares_values() {
+        let a: ConcurrentStack<i32> = (0..5).collect();
+        let b: ConcurrentStack<i32> = (0..5).collect();
+        assert!(a == b);
+        assert!(a == a);
+        b.pop();
+        assert!(a != b);
+        b.push(5);
+        assert!(a != b);
+        b.pop();
+        b.push(4);
+        assert!(a == b);
+        assert!(ConcurrentStack::<i32>::new() == ConcurrentStack::new());
+    }
+
+    #[test]
+    fn zero_sized_values_dont_allocate() {
+        let stack = ConcurrentStack::new();
+        let before = allocs();
+        for _ in 0..1_000_000 {
+            stack.push(());
+        }
+        assert_eq!(stack.len(), 1_000_000);
+        assert!(!stack.is_empty());
+        let mut n = 0;
+        while stack.pop().is_some() {
+            n += 1;
+        }
+        assert_eq!(n, 1_000_000);
+        assert!(stack.is_empty());
+        assert_eq!(allocs(), before);
+    }
+
+    #[test]
+    fn zero_sized_values_dropped_once() {
+        static DROPS: AtomicUsize = AtomicUsize::new(0);
+        #[derive(Clone, Debug, PartialEq)]
+        struct Token;
+        impl Drop for Token {
+            fn drop(&mut self) {
+                DROPS.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let stack = ConcurrentStack::new();
+        stack.push_iter(vec![Token, Token, Token, Token, Token]);
+        assert!(stack.pop().is_some());
+        assert_eq!(DROPS.load(Ordering::SeqCst), 1);
+        assert!(stack.peek().is_some());
+        assert_eq!(DROPS.load(Ordering::SeqCst), 2);
+        assert_eq!(stack.pop_if(|_| false), None);
+        assert!(stack.contains(&Token));
+        assert_eq!(stack.iter_snapshot().len(), 4);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 7);
+        assert_eq!(stack.pop_n(2).len(), 2);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 9);
+        drop(stack);
+        assert_eq!(DROPS.load(Ordering::SeqCst), 11);
+    }
+
+    #[test]
+    fn own_push_is_seen() {
+        let stack = ConcurrentStack::new();
+        for i in 0..10 {
+            stack.push(i);
+            assert!(!stack.is_empty());
+            assert_eq!(stack.pop(), Some(i));
+            assert!(stack.is_empty());
+        }
+    }
+
+    #[test]
+    fn default_is_empty() {
+        let stack: ConcurrentStack<i32> = Default::default();
+        assert!(stack.is_empty());
+        stack.push(1);
+        assert_eq!(stack.pop(), Some(1));
+    }
+
+    #[test]
+    fn debug_shows_len() {
+        let stack: ConcurrentStack<i32> = (0..3).collect();
+        assert_eq!(format!("{:?}", stack), "ConcurrentStack { len: 3, empty: false }");
+        stack.clear();
+        assert_eq!(format!("{:?}", stack), "ConcurrentStack { len: 0, empty: true }");
+    }
+
+    #[test]
+    fn peek_keeps_top() {
+        let stack = ConcurrentStack::new();
+        assert_eq!(stack.peek(), None);
+        stack.push(String::from("a"));
+        stack.push(String::from("b"));
+        assert_eq!(stack.peek(), Some(String::from("b")));
+        assert_eq!(stack.pop(), Some(String::from("b")));
+        assert_eq!(stack.peek(), Some(String::from("a")));
+    }
+
+    #[test]
+    fn peek_while_popping() {
+        let stack = Arc::new(ConcurrentStack::new());
+        for i in 0..10000 {
+            stack.push(vec![i; 4]);
+        }
+
+        let peeker = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                while let Some(v) = stack.peek() {
+                    assert!(v.iter().all(|&x| x == v[0]));
+                }
+            })
+        };
+        let poppers = (0..2)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || while stack.pop().is_some() {})
+                          })
+                          .collect::<Vec<_>>();
+
+        for t in poppers {
+            t.join().unwrap();
+        }
+        peeker.join().unwrap();
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn snapshot_while_churning() {
+        let stack = Arc::new((0..100).map(|i| vec![i; 4]).collect::<ConcurrentStack<_>>());
+        let done = Arc::new(AtomicBool::new(false));
+        let churn = {
+            let stack = stack.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                for i in 0..10000 {
+                    stack.push(vec![i; 4]);
+                    stack.pop();
+                    if i % 2 == 0 {
+                        let v = stack.pop().unwrap();
+                        stack.push(v);
+                    }
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        while !done.load(Ordering::SeqCst) {
+            for v in stack.iter_snapshot() {
+                assert!(v.iter().all(|&x| x == v[0]));
+            }
+        }
+        churn.join().unwrap();
+        let snapshot = stack.iter_snapshot();
+        assert_eq!(snapshot.len(), 100);
+        assert_eq!(snapshot, stack.pop_all());
+    }
+
+    #[test]
+    fn try_into_vec_exact_or_fails() {
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        assert_eq!(stack.try_into_vec(), Ok(vec![4, 3, 2, 1, 0]));
+        assert_eq!(stack.len(), 5);
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(ConcurrentStack::<i32>::new().try_into_vec(), Ok(vec![]));
+
+        // Nothing is popped meanwhile, so exact snapshots only grow, and a failed one
+        // must not lose, duplicate or reorder anything.
+        let stack = Arc::new(ConcurrentStack::new());
+        let pusher = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    stack.push(i);
+                    if i % 10 == 0 {
+                        thread::yield_now();
+                    }
+                }
+            })
+        };
+        let mut seen = 0;
+        while !pusher.is_finished() {
+            if let Ok(mut values) = stack.try_into_vec() {
+                assert!(values.len() >= seen);
+                seen = values.len();
+                values.sort();
+                values.dedup();
+                assert_eq!(values.len(), seen);
+            }
+        }
+        pusher.join().unwrap();
+        assert_eq!(stack.pop_all(), (0..1000).rev().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn try_into_vec_keeps_values_on_panicking_clone() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        #[derive(Debug, PartialEq)]
+        struct Bomb(i32);
+
+        impl Clone for Bomb {
+            fn clone(&self) -> Self {
+                assert!(self.0 != 1, "boom");
+                Bomb(self.0)
+            }
+        }
+
+        let stack: ConcurrentStack<Bomb> = (0..3).map(Bomb).collect();
+        let result = catch_unwind(AssertUnwindSafe(|| stack.try_into_vec()));
+        assert!(result.is_err());
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.pop_all(), vec![Bomb(2), Bomb(1), Bomb(0)]);
+    }
+
+    #[test]
+    fn try_into_vec_puts_back_under_newer_pushes() {
+        #[derive(Debug, PartialEq)]
+        struct Pushing(i32);
+
+        static STACK: ConcurrentStack<Pushing> = ConcurrentStack::new();
+
+        // Pushes while the chain is detached, as if from another thread.
+        impl Clone for Pushing {
+            fn clone(&self) -> Self {
+                if self.0 == 1 {
+                    STACK.push(Pushing(10));
+                    STACK.push(Pushing(11));
+                }
+                Pushing(self.0)
+            }
+        }
+
+        STACK.push_iter((0..3).map(Pushing));
+        assert_eq!(STACK.try_into_vec(), Err(()));
+        assert_eq!(STACK.len(), 5);
+        let values = STACK.pop_all().into_iter().map(|p| p.0).collect::<Vec<_>>();
+        assert_eq!(values, vec![11, 10, 2, 1, 0]);
+    }
+
+    #[test]
+    fn not_closed_while_values_are_detached() {
+        use super::PopResult;
+
+        #[derive(Debug, PartialEq)]
+        struct Popping(i32);
+
+        static STACK: ConcurrentStack<Popping> = ConcurrentStack::new();
+
+        // Tries to pop while `try_into_vec` has the chain detached.
+        impl Clone for Popping {
+            fn clone(&self) -> Self {
+                assert_eq!(STACK.pop_or_closed(), PopResult::Empty);
+                Popping(self.0)
+            }
+        }
+
+        STACK.push_iter((0..3).map(Popping));
+        STACK.close();
+        assert_eq!(STACK.try_into_vec().map(|v| v.len()), Ok(3));
+        assert_eq!(STACK.len(), 3);
+        assert_eq!(STACK.pop_all().len(), 3);
+        assert_eq!(STACK.pop_or_closed(), PopResult::Closed);
+    }
+
+    #[test]
+    fn closed_after_producers_done() {
+        use super::PopResult;
+
+        let stack = Arc::new(ConcurrentStack::new());
+        assert_eq!(stack.pop_or_closed(), PopResult::Empty);
+        let producers = (0..4)
+                            .map(|t| {
+                                let stack = stack.clone();
+                                thread::spawn(move || {
+                                    for i in 0..250 {
+                                        stack.push(t * 250 + i);
+                                    }
+                                })
+                            })
+                            .collect::<Vec<_>>();
+        let closer = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                for p in producers {
+                    p.join().unwrap();
+                }
+                stack.close();
+            })
+        };
+        let mut popped = vec![];
+        loop {
+            match stack.pop_or_closed() {
+                PopResult::Value(v) => popped.push(v),
+                PopResult::Empty => thread::yield_now(),
+                PopResult::Closed => break,
+            }
+        }
+        closer.join().unwrap();
+        popped.sort();
+        assert_eq!(popped, (0..1000).collect::<Vec<_>>());
+        assert!(stack.is_closed());
+        assert_eq!(stack.pop_or_closed(), PopResult::Closed);
+
+        let push = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| stack.push(0)));
+        assert!(push.is_err());
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn contains_present_and_absent() {
+        let stack: ConcurrentStack<String> = (0..5).map(|i| i.to_string()).collect();
+        assert!(stack.contains(&String::from("0")));
+        assert!(stack.contains(&String::from("4")));
+        assert!(!stack.contains(&String::from("5")));
+        stack.pop();
+        assert!(!stack.contains(&String::from("4")));
+        assert!(!ConcurrentStack::new().contains(&0));
+    }
+
+    #[test]
+    fn len_follows_push_pop() {
+        let stack = ConcurrentStack::new();
+        assert_eq!(stack.len(), 0);
+        stack.push(1);
+        stack.push(2);
+        assert_eq!(stack.len(), 2);
+        stack.pop();
+        assert_eq!(stack.len(), 1);
+        stack.pop();
+        stack.pop();
+        assert_eq!(stack.len(), 0);
+    }
+
+    #[test]
+    fn len_hint_within_bounds() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let worker = {
+            let stack = stack.clone();
+            thread::spawn(move || {
+                for i in 0..1000 {
+                    stack.push(i);
+                    if i % 2 == 0 {
+                        stack.pop();
+                    }
+                }
+            })
+        };
+        while !worker.is_finished() {
+            assert!(stack.len_hint() <= 1000);
+        }
+        worker.join().unwrap();
+        // Once the worker has been joined the counter is exact.
+        assert_eq!(stack.len_hint(), 500);
+        stack.pop_n(200);
+        assert_eq!(stack.len_hint(), 300);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn preallocated_pushes_dont_allocate() {
+        let stack = ConcurrentStack::with_preallocated(100);
+        // Takes the first hazard slot and allocates the cell of the top, if any.
+        stack.push(0);
+        assert_eq!(stack.pop(), Some(0));
+
+        let before = allocs();
+        for i in 0..100 {
+            stack.push(i);
+        }
+        assert_eq!(allocs(), before);
+        stack.push(100);
+        assert_eq!(allocs(), before + 1);
+    }
+
+    #[cfg(feature = "no-recycle")]
+    #[test]
+    fn no_recycle_frees_nodes() {
+        let stack = ConcurrentStack::with_preallocated(4);
+        // Takes the first hazard slot and allocates the cell of the top, if any.
+        stack.push(0);
+        assert_eq!(stack.pop(), Some(0));
+
+        let (allocated, freed) = (allocs(), frees());
+        for i in 0..4 {
+            stack.push(i);
+        }
+        assert_eq!(allocs(), allocated + 4);
+        for i in (0..4).rev() {
+            assert_eq!(stack.pop(), Some(i));
+        }
+        assert_eq!(frees(), freed + 4);
+        stack.push(4);
+        assert_eq!(allocs(), allocated + 5);
+        assert_eq!(stack.shrink(), 0);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn reserved_pushes_dont_allocate() {
+        let stack = ConcurrentStack::new();
+        for i in 0..10 {
+            stack.push(i);
+        }
+        assert_eq!(stack.pop_n(5).len(), 5);
+        stack.reserve(50);
+
+        let before = allocs();
+        for i in 0..55 {
+            stack.push(i);
+        }
+        assert_eq!(allocs(), before);
+        stack.push(55);
+        assert_eq!(allocs(), before + 1);
+    }
+
+    #[cfg(feature = "allocator_api")]
+    #[test]
+    fn allocator_sees_every_node() {
+        use std::alloc::{AllocError, Allocator, Global};
+        use std::ptr::NonNull;
+
+        #[derive(Default)]
+        struct Counting {
+            allocs: AtomicUsize,
+            frees: AtomicUsize,
+        }
+
+        unsafe impl Allocator for Counting {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                self.allocs.fetch_add(1, Ordering::SeqCst);
+                Global.allocate(layout)
+            }
+
+            unsafe fn deallocate(&self, p: NonNull<u8>, layout: Layout) {
+                self.frees.fetch_add(1, Ordering::SeqCst);
+                Global.deallocate(p, layout)
+            }
+        }
+
+        let counting = Counting::default();
+        let counts = || {
+            (counting.allocs.load(Ordering::SeqCst), counting.frees.load(Ordering::SeqCst))
+        };
+        {
+            let stack = ConcurrentStack::new_in(&counting);
+            stack.push_iter(0..10);
+            assert_eq!(counts(), (10, 0));
+            assert_eq!(stack.pop_n(4).len(), 4);
+            stack.push_iter(0..4);
+            assert_eq!(counts(), (10, 0));
+            stack.reserve(6);
+            assert_eq!(stack.shrink(), 6);
+            assert_eq!(counts(), (16, 6));
+
+            let mut chain = stack.clone().take_chain();
+            assert_eq!(chain.next(), Some(3));
+            let doubled = stack.clone().map(|v| v * 2);
+            assert_eq!(doubled.pop(), Some(6));
+            thread::scope(|s| {
+                for t in 0..4 {
+                    let stack = &stack;
+                    s.spawn(move || {
+                        for i in 0..100 {
+                            stack.push(t * 100 + i);
+                            stack.pop();
+                        }
+                    });
+                }
+            });
+            assert_eq!(stack.len(), 10);
+        }
+        let (allocs, frees) = counts();
+        assert!(allocs >= 36, "{}", allocs);
+        assert_eq!(allocs, frees);
+
+        let stack = ConcurrentStack::<i32, EpochReclaim, _>::with_reclaim_in(&counting);
+        stack.push(1);
+        drop(stack);
+        assert_eq!(counts(), (allocs + 1, frees + 1));
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn reserve_to_tops_up_once() {
+        let stack = ConcurrentStack::new();
+        for i in 0..10 {
+            stack.push(i);
+        }
+        assert_eq!(stack.pop_n(3).len(), 3);
+        let before = allocs();
+        stack.reserve_to(8);
+        assert_eq!(allocs(), before + 5);
+        assert_eq!(stack.trash_len(), 8);
+        stack.reserve_to(8);
+        stack.reserve_to(2);
+        assert_eq!(allocs(), before + 5);
+        assert_eq!(stack.trash_len(), 8);
+
+        let before = allocs();
+        for i in 0..8 {
+            stack.push(i);
+        }
+        assert_eq!(allocs(), before);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn trash_len_follows_recycling() {
+        let stack = ConcurrentStack::with_trash_limit(4);
+        assert_eq!(stack.trash_limit(), 4);
+        assert_eq!(stack.capacity(), usize::MAX);
+        for i in 0..6 {
+            stack.push(i);
+        }
+        assert_eq!(stack.trash_len(), 0);
+        assert_eq!(stack.pop_n(3).len(), 3);
+        assert_eq!(stack.trash_len(), 3);
+        // The trash is full after one more, the rest are freed.
+        assert_eq!(stack.pop_n(3).len(), 3);
+        assert_eq!(stack.trash_len(), 4);
+        stack.push(0);
+        assert_eq!(stack.trash_len(), 3);
+        assert_eq!(stack.shrink(), 3);
+        assert_eq!(stack.trash_len(), 0);
+        assert_eq!(ConcurrentStack::<i32>::with_capacity(8).capacity(), 8);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn recycle_policy_decides() {
+        use super::RecyclePolicy;
+
+        struct Never;
+        struct Always;
+
+        impl RecyclePolicy for Never {
+            fn should_recycle(&self, _trash_len: usize) -> bool {
+                false
+            }
+        }
+
+        impl RecyclePolicy for Always {
+            fn should_recycle(&self, _trash_len: usize) -> bool {
+                true
+            }
+        }
+
+        let stack = ConcurrentStack::with_recycle_policy(Never);
+        stack.push_iter(0..100);
+        assert_eq!(stack.pop_n(100).len(), 100);
+        assert_eq!(stack.trash_len(), 0);
+
+        // Past the default trash limit, which the policy replaces.
+        let n = super::DEFAULT_TRASH_LIMIT + 100;
+        let stack = ConcurrentStack::with_recycle_policy(Always);
+        stack.push_iter(0..n);
+        assert_eq!(stack.pop_all().len(), n);
+        assert_eq!(stack.trash_len(), n);
+        // The clone asks the same policy.
+        let copy = stack.clone();
+        copy.push(0);
+        copy.pop();
+        assert_eq!(copy.trash_len(), 1);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn fifo_trash_reuses_oldest_first() {
+        // Push and pop in rounds, recording the node every push landed in and the
+        // allocations and frees after every operation.
+        fn script(stack: &ConcurrentStack<usize>) -> (Vec<usize>, Vec<(usize, usize)>) {
+            let (a, f) = (allocs(), frees());
+            let mut nodes = vec![];
+            let mut counts = vec![];
+            for round in 0..4 {
+                for i in 0..3 + round {
+                    stack.push(i);
+                    nodes.push(stack.top.load().0 as usize);
+                    counts.push((allocs() - a, frees() - f));
+                }
+                for _ in 0..2 + round {
+                    stack.pop();
+                    counts.push((allocs() - a, frees() - f));
+                }
+            }
+            stack.shrink();
+            counts.push((allocs() - a, frees() - f));
+            (nodes, counts)
+        }
+
+        let stack = ConcurrentStack::with_fifo_trash();
+        for i in 0..3 {
+            stack.push(i);
+        }
+        let nodes = (0..3).map(|_| {
+                              let node = stack.top.load().0;
+                              stack.pop();
+                              node
+                          })
+                          .collect::<Vec<_>>();
+        // Trashed as 2, 1, 0, so reused in that order.
+        for (i, &node) in nodes.iter().enumerate() {
+            stack.push(i);
+            assert_eq!(stack.top.load().0, node);
+        }
+        assert_eq!(stack.trash_len(), 0);
+
+        // The first run warms up the thread's hazard slots.
+        script(&ConcurrentStack::with_fifo_trash());
+        let (first, counts) = script(&ConcurrentStack::with_fifo_trash());
+        let (second, again) = script(&ConcurrentStack::with_fifo_trash());
+        assert_eq!(counts, again);
+        // The nodes of both runs were reused in the same order.
+        let rank = |nodes: &[usize]| {
+            nodes.iter().map(|n| nodes.iter().position(|m| m == n).unwrap()).collect::<Vec<_>>()
+        };
+        assert_eq!(rank(&first), rank(&second));
+
+        let stack = ConcurrentStack::with_fifo_trash();
+        stack.reserve(16);
+        churn_with(stack);
+    }
+
+    #[cfg(all(feature = "std", not(feature = "no-recycle")))]
+    #[test]
+    fn local_cache_spares_trash() {
+        // Every CAS on the trash head bumps its stamp.
+        fn trash_traffic(stack: ConcurrentStack<usize>) -> usize {
+            let stack = Arc::new(stack);
+            stack.reserve(64);
+            let before = stack.trash.load().1;
+            let workers = (0..4)
+                              .map(|t| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || {
+                                      for i in 0..1000 {
+                                          stack.push(t * 1000 + i);
+                                          stack.pop();
+                                      }
+                                  })
+                              })
+                              .collect::<Vec<_>>();
+            for w in workers {
+                w.join().unwrap();
+            }
+            stack.trash.load().1.wrapping_sub(before)
+        }
+
+        let shared = trash_traffic(ConcurrentStack::new());
+        let cached = trash_traffic(ConcurrentStack::with_local_cache());
+        assert!(shared >= 4000, "{}", shared);
+        assert!(cached * 10 < shared, "{} vs {}", cached, shared);
+    }
+
+    #[cfg(all(feature = "std", not(feature = "no-recycle")))]
+    #[test]
+    fn local_cache_survives_thread_exit() {
+        let stack = Arc::new(ConcurrentStack::<usize>::with_local_cache());
+        for round in 0..4 {
+            // Each thread leaves values behind and exits with a cache full of nodes.
+            let workers = (0..4)
+                              .map(|t| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || {
+                                      let mut popped = vec![];
+                                      for i in 0..100 {
+                                          stack.push((round * 4 + t) * 100 + i);
+                                          if i % 3 != 0 {
+                                              popped.extend(stack.pop());
+                                          }
+                                      }
+                                      popped
+                                  })
+                              })
+                              .collect::<Vec<_>>();
+            let mut seen = workers.into_iter()
+                                  .flat_map(|w| w.join().unwrap())
+                                  .collect::<Vec<_>>();
+            seen.extend(stack.pop_all());
+            seen.sort();
+            assert_eq!(seen, ((round * 4) * 100..(round * 4 + 4) * 100).collect::<Vec<_>>());
+        }
+
+        // Cached nodes of one stack serve any other with the same node layout.
+        let other = ConcurrentStack::<isize>::with_local_cache();
+        stack.push(1);
+        let node = stack.top.load().0 as usize;
+        stack.pop();
+        other.push(-1);
+        assert_eq!(other.top.load().0 as usize, node);
+        assert_eq!(other.pop(), Some(-1));
+    }
+
+    #[test]
+    fn current_top_null_when_empty() {
+        let stack = ConcurrentStack::new();
+        let (p, v) = unsafe { stack.current_top() };
+        assert!(p.is_null());
+        stack.push(1);
+        let (q, w) = unsafe { stack.current_top() };
+        assert!(!q.is_null());
+        assert_ne!(v, w);
+        stack.pop();
+        assert!(unsafe { stack.current_top() }.0.is_null());
+    }
+
+    #[cfg(all(feature = "std", not(feature = "no-recycle")))]
+    #[test]
+    fn background_reclaim_trims_idle_trash() {
+        use std::time::{Duration, Instant};
+
+        let stack = ConcurrentStack::with_background_reclaim(Duration::from_millis(5), 8);
+        stack.push_iter(0..100);
+        while stack.pop().is_some() {}
+        assert_eq!(stack.trash_len(), 100);
+        let start = Instant::now();
+        while stack.trash_len() > 8 {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(stack.trash_len(), 8);
+        stack.push(1);
+        assert_eq!(stack.pop(), Some(1));
+
+        let other = ConcurrentStack::<i32>::new();
+        other.reserve(10);
+        assert_eq!(other.shrink_to(4), 6);
+        assert_eq!(other.trash_len(), 4);
+        // Dropping the stack joins the thread.
+        drop(stack);
+    }
+
+    #[test]
+    fn static_stack_shared() {
+        static STACK: ConcurrentStack<u64> = ConcurrentStack::new();
+        static EPOCH: ConcurrentStack<u64, EpochReclaim> = ConcurrentStack::with_reclaim();
+
+        // Nothing is allocated before the first write.
+        assert!(STACK.is_empty());
+        assert_eq!(STACK.pop(), None);
+        let workers = (0..4u64)
+                          .map(|t| {
+                              thread::spawn(move || {
+                                  let mut popped = 0;
+                                  for i in 0..1000 {
+                                      STACK.push(t * 1000 + i);
+                                      popped += STACK.pop().unwrap();
+                                      EPOCH.push(i);
+                                  }
+                                  popped
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let popped: u64 = workers.into_iter().map(|w| w.join().unwrap()).sum();
+        assert_eq!(popped, (0..4000).sum::<u64>());
+        assert!(STACK.is_empty());
+        assert_eq!(EPOCH.len(), 4000);
+        assert_eq!(EPOCH.pop_all().into_iter().sum::<u64>(), 4 * (0..1000).sum::<u64>());
+    }
+
+    #[test]
+    fn split_off_keeps_top() {
+        let stack = ConcurrentStack::new();
+        stack.push_iter(0..10);
+        let bottom = stack.split_off(3);
+        assert_eq!(stack.len(), 3);
+        assert_eq!(bottom.len(), 7);
+        assert_eq!(stack.pop_all(), vec![9, 8, 7]);
+        assert_eq!(bottom.pop_all(), vec![6, 5, 4, 3, 2, 1, 0]);
+
+        stack.push_iter(0..3);
+        assert!(stack.split_off(3).is_empty());
+        assert!(stack.split_off(5).is_empty());
+        let all = stack.split_off(0);
+        assert!(stack.is_empty());
+        assert_eq!(all.pop_all(), vec![2, 1, 0]);
+
+        let units = ConcurrentStack::new();
+        units.push_iter(vec![(); 10]);
+        assert_eq!(units.split_off(3).len(), 7);
+        assert_eq!(units.len(), 3);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn shrink_frees_trash() {
+        let stack = ConcurrentStack::new();
+        stack.push_iter(0..100);
+        while stack.pop().is_some() {}
+        assert_eq!(stack.trash_len.load(Ordering::Relaxed), 100);
+
+        let before = frees();
+        assert_eq!(stack.shrink(), 100);
+        assert_eq!(frees() - before, 100);
+        assert_eq!(stack.trash_len.load(Ordering::Relaxed), 0);
+        assert_eq!(stack.shrink(), 0);
+
+        stack.push(1);
+        assert_eq!(stack.pop(), Some(1));
+    }
+
+    #[test]
+    fn node_layout_matches() {
+        use super::Node;
+
+        assert_eq!(ConcurrentStack::<u8>::node_layout(), Layout::new::<Node<u8>>());
+        assert_eq!(ConcurrentStack::<String>::node_layout(), Layout::new::<Node<String>>());
+        assert_eq!(ConcurrentStack::<[u64; 4], EpochReclaim>::node_layout(),
+                   Layout::new::<Node<[u64; 4]>>());
+        // Usable in constants, like the size of a C side slab slot.
+        const SLOT: usize = ConcurrentStack::<u64>::node_layout().size();
+        assert!(SLOT >= core::mem::size_of::<Option<u64>>() + core::mem::size_of::<usize>());
+        let align = core::mem::align_of::<u64>().max(core::mem::align_of::<usize>());
+        assert_eq!(ConcurrentStack::<u64>::node_layout().align(), align);
+    }
+
+    #[test]
+    fn len_slow_counts_nodes() {
+        let stack: ConcurrentStack<i32> = (0..10).collect();
+        assert_eq!(stack.len_slow(), 10);
+        stack.pop();
+        assert_eq!(stack.len_slow(), 9);
+        stack.clear();
+        assert_eq!(stack.len_slow(), 0);
+    }
+
+    #[test]
+    fn into_iter_pops_in_order() {
+        let stack = ConcurrentStack::new();
+        stack.push(1);
+        stack.push(2);
+        stack.push(3);
+        assert_eq!(stack.into_iter().collect::<Vec<_>>(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn vec_round_trip_reverses() {
+        let stack = ConcurrentStack::from_vec(vec![1, 2, 3]);
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.into_vec(), vec![3, 2, 1]);
+        assert_eq!(ConcurrentStack::<i32>::from_vec(Vec::new()).into_vec(), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn fold_consumes_in_pop_order() {
+        let stack = ConcurrentStack::from_vec((1..=10).collect());
+        assert_eq!(stack.fold(0, |sum, v| sum + v), 55);
+        let stack = ConcurrentStack::from_vec(vec!['a', 'b', 'c']);
+        assert_eq!(stack.fold(String::new(), |mut s, c| {
+                             s.push(c);
+                             s
+                         }),
+                   "cba");
+        // Every value is handed to `f`, none is left behind in the stack.
+        let shared = Arc::new(());
+        let stack = ConcurrentStack::new();
+        stack.push_iter((0..4).map(|_| shared.clone()));
+        assert_eq!(stack.fold(0, |n, v| {
+                             drop(v);
+                             n + 1
+                         }),
+                   4);
+        assert_eq!(Arc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn shared_iter_drains_while_fed() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let done = Arc::new(AtomicBool::new(false));
+        let producer = {
+            let stack = stack.clone();
+            let done = done.clone();
+            thread::spawn(move || {
+                for i in 0..10000 {
+                    stack.push(i);
+                }
+                done.store(true, Ordering::SeqCst);
+            })
+        };
+        let mut sum = 0;
+        let mut finished = false;
+        while !finished {
+            finished = done.load(Ordering::SeqCst);
+            for v in &*stack {
+                sum += v;
+            }
+        }
+        producer.join().unwrap();
+        assert!(stack.is_empty());
+        assert_eq!(sum, (0..10000).sum::<i32>());
+    }
+
+    #[test]
+    fn collect_pushes_in_order() {
+        let stack: ConcurrentStack<i32> = (0..10).collect();
+        for i in (0..10).rev() {
+            assert_eq!(stack.pop(), Some(i));
+        }
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn extend_pushes_all() {
+        let mut stack = ConcurrentStack::new();
+        stack.extend(0..1000);
+        assert_eq!(stack.len(), 1000);
+        for i in (0..1000).rev() {
+            assert_eq!(stack.pop(), Some(i));
+        }
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn push_iter_keeps_order() {
+        let stack = ConcurrentStack::new();
+        stack.push(0);
+        stack.push_iter(1..4);
+        stack.push_iter(Vec::new());
+        assert_eq!(stack.len(), 4);
+        assert_eq!(stack.pop_all(), vec![3, 2, 1, 0]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_keeps_values_of_closed_stack() {
+        let stack: ConcurrentStack<i32> = (1..4).collect();
+        stack.close();
+        assert_eq!(::serde_json::to_string(&stack).unwrap(), "[3,2,1]");
+        assert_eq!(stack.pop_all(), vec![3, 2, 1]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_keeps_values_on_panic() {
+        use serde::{Serialize, Serializer};
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        #[derive(Clone, Debug, PartialEq)]
+        struct Bomb(i32);
+
+        impl Serialize for Bomb {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                assert!(self.0 != 1, "boom");
+                self.0.serialize(serializer)
+            }
+        }
+
+        let stack: ConcurrentStack<Bomb> = (0..3).map(Bomb).collect();
+        assert!(catch_unwind(AssertUnwindSafe(|| ::serde_json::to_string(&stack))).is_err());
+        assert_eq!(stack.pop_all(), vec![Bomb(2), Bomb(1), Bomb(0)]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        let stack: ConcurrentStack<i32> = (1..4).collect();
+        let json = ::serde_json::to_string(&stack).unwrap();
+        assert_eq!(json, "[3,2,1]");
+        assert_eq!(stack.len(), 3);
+
+        let back: ConcurrentStack<i32> = ::serde_json::from_str(&json).unwrap();
+        assert_eq!(back.pop_all(), vec![3, 2, 1]);
+        assert_eq!(stack.pop_all(), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn try_push_respects_capacity() {
+        let stack = ConcurrentStack::with_capacity(2);
+        assert_eq!(stack.try_push(1), Ok(()));
+        assert_eq!(stack.try_push(2), Ok(()));
+        assert_eq!(stack.try_push(3), Err(3));
+        assert_eq!(stack.pop(), Some(2));
+        assert_eq!(stack.try_push(3), Ok(()));
+        assert_eq!(stack.len(), 2);
+    }
+
+    #[test]
+    fn try_push_alloc_hands_back_on_oom() {
+        let stack = ConcurrentStack::new();
+        stack.push(String::from("a"));
+        // Recycles the node of "a".
+        assert_eq!(stack.pop().as_deref(), Some("a"));
+        let (b, c) = (String::from("b"), String::from("c"));
+        if super::RECYCLE {
+            assert_eq!(out_of_memory(|| stack.try_push_alloc(b)), Ok(()));
+        } else {
+            stack.push(b);
+        }
+        assert_eq!(out_of_memory(|| stack.try_push_alloc(c)), Err(String::from("c")));
+        assert_eq!(stack.len(), 1);
+        assert_eq!(stack.try_push_alloc(String::from("d")), Ok(()));
+        assert_eq!(stack.pop_all(), vec!["d", "b"]);
+
+        // A node in the cache of the thread comes before the trash, in a new thread so
+        // that the cache is empty to begin with.
+        #[cfg(all(feature = "std", not(feature = "no-recycle")))]
+        thread::spawn(|| {
+            let stack = ConcurrentStack::with_local_cache();
+            stack.push(String::from("e"));
+            assert_eq!(stack.pop().as_deref(), Some("e"));
+            assert_eq!(stack.trash_len(), 0);
+            let f = String::from("f");
+            assert_eq!(out_of_memory(|| stack.try_push_alloc(f)), Ok(()));
+            assert_eq!(stack.pop_all(), vec!["f"]);
+        }).join().unwrap();
+    }
+
+    #[test]
+    fn try_push_concurrent_capacity() {
+        let stack = Arc::new(ConcurrentStack::with_capacity(50));
+        let pushers = (0..8)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || (0..100).filter(|&i| stack.try_push(i).is_ok()).count())
+                          })
+                          .collect::<Vec<_>>();
+
+        let pushed: usize = pushers.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(pushed, 50);
+        assert_eq!(stack.len(), 50);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn trash_limit_frees_extra_nodes() {
+        let stack = ConcurrentStack::with_trash_limit(2);
+        for i in 0..10 {
+            stack.push(i);
+        }
+        while stack.pop().is_some() {}
+        assert_eq!(stack.trash_len.load(Ordering::Relaxed), 2);
+
+        let stack = ConcurrentStack::with_trash_limit(0);
+        stack.push(1);
+        stack.pop();
+        assert!(stack.pick_trash().is_null());
+    }
+
+    #[test]
+    fn trash_limit_under_contention() {
+        let stack = Arc::new(ConcurrentStack::with_trash_limit(4));
+        let threads = (0..8)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..1000 {
+                                      stack.push(i);
+                                      stack.push(i);
+                                      assert!(stack.pop().is_some());
+                                      assert!(stack.pop().is_some());
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(stack.is_empty());
+    }
+
+    #[test]
+    fn no_recycling_under_contention() {
+        // Every popped node is freed straight away, so a thread reading `next` of a
+        // node popped by another thread would hit freed memory without hazard
+        // pointers.
+        let stack = Arc::new(ConcurrentStack::with_trash_limit(0));
+        let threads = (0..8)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..2000 {
+                                      stack.push(Box::new(i));
+                                      stack.push(Box::new(i));
+                                      assert!(stack.pop().is_some());
+                                      if let Some(v) = stack.peek() {
+                                          assert!(*v < 2000);
+                                      }
+                                      assert!(stack.pop().is_some());
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(stack.is_empty());
+    }
+
+    // Run pushes and pops of unique values on several threads, with a SeqCst clock
+    // read right before and after each of them, and check the history against every
+    // linearization the crate docs promise.
+    fn check_linearizable(stack: ConcurrentStack<usize>) {
+        #[derive(Clone, Copy)]
+        struct Op {
+            start: usize,
+            end: usize,
+        }
+
+        let clock = Arc::new(AtomicUsize::new(0));
+        let stack = Arc::new(stack);
+        let workers = (0..4)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              let clock = clock.clone();
+                              thread::spawn(move || {
+                                  let (mut pushes, mut pops, mut empty) = (vec![], vec![], vec![]);
+                                  for i in 0..300 {
+                                      let start = clock.fetch_add(1, Ordering::SeqCst);
+                                      if (i + t) % 3 == 0 {
+                                          let v = stack.pop();
+                                          let end = clock.fetch_add(1, Ordering::SeqCst);
+                                          let op = Op { start, end };
+                                          match v {
+                                              Some(v) => pops.push((v, op)),
+                                              None => empty.push(op),
+                                          }
+                                      } else {
+                                          let v = t * 1000 + i;
+                                          stack.push(v);
+                                          let end = clock.fetch_add(1, Ordering::SeqCst);
+                                          pushes.push((v, Op { start, end }));
+                                      }
+                                  }
+                                  (pushes, pops, empty)
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let mut pushed = std::collections::HashMap::new();
+        let mut popped = std::collections::HashMap::new();
+        let mut empty = vec![];
+        for w in workers {
+            let (pu, po, em) = w.join().unwrap();
+            pushed.extend(pu);
+            popped.extend(po);
+            empty.extend(em);
+        }
+        while let Some(v) = stack.pop() {
+            let at = clock.fetch_add(1, Ordering::SeqCst);
+            popped.insert(v, Op { start: at, end: at });
+        }
+        assert_eq!(pushed.len(), popped.len());
+
+        for (v, pop) in &popped {
+            let push = pushed[v];
+            // Popped no earlier than pushed.
+            assert!(pop.end > push.start);
+            // A value surely pushed on top of `v` before it was popped, must come off
+            // first.
+            for (w, above) in &pushed {
+                if above.start > push.end && above.end < pop.start {
+                    assert!(popped[w].start < pop.end, "{} popped before {}", v, w);
+                }
+            }
+        }
+        // A pop found the stack empty only if no value was surely on it all along.
+        for op in &empty {
+            for (v, push) in &pushed {
+                assert!(!(push.end < op.start && popped[v].start > op.end),
+                        "{} was on the stack during an empty pop",
+                        v);
+            }
+        }
+    }
+
+    #[test]
+    fn linearizable_history() {
+        check_linearizable(ConcurrentStack::new());
+        check_linearizable(ConcurrentStack::with_elimination());
+    }
+
+    #[test]
+    fn contended_push_pop() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..16)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut popped = 0;
+                                  for i in 0..5000 {
+                                      stack.push(i);
+                                      if stack.pop().is_some() {
+                                          popped += 1;
+                                      }
+                                  }
+                                  popped
+                              })
+                          })
+                          .collect::<Vec<_>>();
+
+        let popped: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(popped + stack.len(), 16 * 5000);
+    }
+
+    #[test]
+    fn elimination_balanced() {
+        // With the counters, the load is run again until a pop took a value from a push
+        // through the array. That needs pushes and pops running at the same time: on a
+        // single core a parked push is never met while it waits, so one round it is.
+        for round in 0.. {
+            let stack = Arc::new(ConcurrentStack::with_elimination());
+            let producers = (0..4)
+                                .map(|_| {
+                                    let stack = stack.clone();
+                                    thread::spawn(move || {
+                                        for i in 0..10000 {
+                                            stack.push(i);
+                                        }
+                                    })
+                                })
+                                .collect::<Vec<_>>();
+            let consumers = (0..4)
+                                .map(|_| {
+                                    let stack = stack.clone();
+                                    thread::spawn(move || {
+                                        let mut sum = 0;
+                                        for _ in 0..10000 {
+                                            sum += stack.pop().unwrap_or(0);
+                                        }
+                                        sum
+                                    })
+                                })
+                                .collect::<Vec<_>>();
+
+            for t in producers {
+                t.join().unwrap();
+            }
+            let popped: i32 = consumers.into_iter().map(|t| t.join().unwrap()).sum();
+            let rest: i32 = stack.pop_all().into_iter().sum();
+            assert_eq!(popped + rest, 4 * 49995000);
+
+            #[cfg(feature = "stats")]
+            let done = stack.stats().eliminations > 0 ||
+                       thread::available_parallelism().map_or(true, |n| n.get() == 1);
+            #[cfg(not(feature = "stats"))]
+            let done = true;
+            if done {
+                break;
+            }
+            assert!(round < 20, "no value went through the elimination array");
+        }
+    }
+
+    #[test]
+    fn push_pop_while_recycling() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..8)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..5000 {
+                                      stack.push_iter(vec![i; 8]);
+                                      for _ in 0..8 {
+                                          assert!(stack.pop().is_some());
+                                      }
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert!(stack.is_empty());
+        assert!(stack.trash_len.load(Ordering::Relaxed) <= 64);
+    }
+
+    #[test]
+    fn shared_between_threads() {
+        fn assert_send_sync<S: Send + Sync>(_: &S) {}
+
+        let stack = Arc::new(ConcurrentStack::new());
+        assert_send_sync(&stack);
+        let t = {
+            let stack = stack.clone();
+            thread::spawn(move || stack.push(String::from("moved")))
+        };
+        t.join().unwrap();
+        assert_eq!(stack.pop(), Some(String::from("moved")));
+    }
+
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn forgotten_stack_leaks_safely() {
+        use std::sync::atomic::AtomicPtr;
+
+        // Keeps the forgotten stack reachable, so that Miri and LeakSanitizer don't
+        // report the leak.
+        static FORGOTTEN: AtomicPtr<ConcurrentStack<Counted>> =
+            AtomicPtr::new(std::ptr::null_mut());
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = Box::new(ConcurrentStack::new());
+        let other = ConcurrentStack::new();
+        for _ in 0..3 {
+            stack.push(Counted(drops.clone()));
+            other.push(Counted(drops.clone()));
+        }
+        drop(stack.pop());
+        // The nodes of `other` now belong to `stack`, and are forgotten with it.
+        stack.append(&other);
+        other.push(Counted(drops.clone()));
+        let freed = frees();
+        FORGOTTEN.store(&*stack as *const _ as *mut _, Ordering::SeqCst);
+        std::mem::forget(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert_eq!(frees(), freed);
+
+        // The other stack is untouched.
+        assert_eq!(other.len(), 1);
+        drop(other.pop());
+        assert!(other.pop().is_none());
+        drop(other);
+        assert_eq!(drops.load(Ordering::SeqCst), 2);
+
+        // Nothing was torn down, the forgotten stack is still whole.
+        let forgotten = unsafe { &*FORGOTTEN.load(Ordering::SeqCst) };
+        assert_eq!(forgotten.len(), 5);
+        assert_eq!(forgotten.len_slow(), 5);
+    }
+
+    #[cfg(not(feature = "no-recycle"))]
+    #[test]
+    fn trashed_values_dropped() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = ConcurrentStack::with_trash_limit(2);
+        // Unlike `new_node` this never takes a node back out of the trash.
+        let trash_value = |stack: &ConcurrentStack<Counted>| {
+            let node = stack.alloc_node();
+            unsafe {
+                (*node).data.with_mut(|d| *d = Some(Counted(drops.clone())));
+            }
+            stack.put_trash(node);
+        };
+        for _ in 0..3 {
+            trash_value(&stack);
+        }
+        // The trash was full for the last node, which was freed at once.
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+        assert_eq!(stack.shrink(), 2);
+        assert_eq!(drops.load(Ordering::SeqCst), 3);
+        trash_value(&stack);
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn drop_frees_all_despite_panic() {
+        struct Bomb(bool, Arc<AtomicUsize>);
+
+        impl Drop for Bomb {
+            fn drop(&mut self) {
+                self.1.fetch_add(1, Ordering::SeqCst);
+                if self.0 {
+                    panic!("bomb");
+                }
+            }
+        }
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = ConcurrentStack::new();
+        for i in 0..10 {
+            stack.push(Bomb(i == 4, drops.clone()));
+        }
+        for _ in 0..3 {
+            stack.push(Bomb(false, drops.clone()));
+            drop(stack.pop());
+        }
+        let dropped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(stack)));
+        assert!(dropped.is_err());
+        // The values below the panicking one were dropped too. The panic allocates, so
+        // that every node was freed, the trashed one too, is left to the leak checker of
+        // the sanitizer runs.
+        assert_eq!(drops.load(Ordering::SeqCst), 13);
+    }
+
+    #[test]
+    fn clear_drops_all() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = Arc::new(ConcurrentStack::new());
+
+        let pusher = {
+            let stack = stack.clone();
+            let drops = drops.clone();
+            thread::spawn(move || {
+                for _ in 0..10000 {
+                    stack.push(Counted(drops.clone()));
                 }
             })
         };
+        for _ in 0..100 {
+            stack.clear();
+        }
+        pusher.join().unwrap();
 
-        for t in input_p {
-            t.join().unwrap();
+        stack.clear();
+        assert!(stack.is_empty());
+        assert_eq!(stack.len(), 0);
+        assert_eq!(drops.load(Ordering::SeqCst), 10000);
+
+        stack.push(Counted(drops.clone()));
+        assert_eq!(stack.len(), 1);
+        assert!(stack.pop().is_some());
+    }
+
+    #[test]
+    fn values_dropped_exactly_once() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack = ConcurrentStack::new();
+        for _ in 0..100 {
+            stack.push(Counted(drops.clone()));
         }
-        output_p.join().unwrap();
+        for _ in 0..40 {
+            drop(stack.pop());
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 40);
 
-        assert!(stack.empty());
+        // Recycled nodes must not drop anything again.
+        for _ in 0..20 {
+            stack.push(Counted(drops.clone()));
+        }
+        drop(stack);
+        assert_eq!(drops.load(Ordering::SeqCst), 120);
     }
 
     #[test]
-    fn store_uncopyable() {
+    fn append_moves_all() {
+        let stack: ConcurrentStack<i32> = vec![1, 2].into_iter().collect();
+        let other: ConcurrentStack<i32> = vec![3, 4, 5].into_iter().collect();
+        let allocated = allocs();
+        stack.append(&other);
+        assert_eq!(allocs(), allocated);
+        assert!(other.is_empty());
+        assert_eq!(other.len(), 0);
+        assert_eq!(stack.len(), 5);
+        assert_eq!(stack.pop_all(), vec![5, 4, 3, 2, 1]);
+    }
+
+    #[test]
+    fn drain_guard_clears_rest() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack: ConcurrentStack<Counted> = (0..5).map(|_| Counted(drops.clone())).collect();
+        {
+            let mut drain = stack.drain();
+            drop(drain.next());
+            drop(drain.next());
+            assert_eq!(drops.load(Ordering::SeqCst), 2);
+            assert_eq!(stack.len(), 3);
+        }
+        assert_eq!(drops.load(Ordering::SeqCst), 5);
+        assert!(stack.is_empty());
+
+        stack.push(Counted(drops.clone()));
+        assert_eq!(stack.drain().count(), 1);
+        assert_eq!(drops.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn count_drained_drops_all() {
+        let drops = Arc::new(AtomicUsize::new(0));
+        let stack: ConcurrentStack<Counted> = (0..10).map(|_| Counted(drops.clone())).collect();
+        assert_eq!(stack.count_drained(), 10);
+        assert_eq!(drops.load(Ordering::SeqCst), 10);
+        assert!(stack.is_empty());
+        assert_eq!(stack.len(), 0);
+        assert_eq!(stack.count_drained(), 0);
+
+        let units: ConcurrentStack<()> = (0..3).map(|_| ()).collect();
+        assert_eq!(units.count_drained(), 3);
+        assert!(units.is_empty());
+    }
+
+    #[test]
+    fn extend_shared_from_threads() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..4)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  for i in 0..100 {
+                                      (&*stack).extend((0..10).map(|j| t * 1000 + i * 10 + j));
+                                  }
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let mut all = stack.pop_all();
+        all.sort();
+        let mut expected = (0..4).flat_map(|t| (0..1000).map(move |i| t * 1000 + i)).collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(all, expected);
+    }
+
+    #[test]
+    fn extend_from_slice_copies() {
         let stack = ConcurrentStack::new();
-        stack.push(Box::new(1));
-        stack.push(Box::new(2));
-        stack.push(Box::new(3));
-        assert_eq!(*stack.pop().unwrap(), 3);
-        assert_eq!(*stack.pop().unwrap(), 2);
-        assert_eq!(*stack.pop().unwrap(), 1);
+        let items = [1, 2, 3];
+        stack.push(0);
+        stack.extend_from_slice(&items);
+        stack.extend_from_slice(&[]);
+        assert_eq!(stack.len(), 4);
+        assert_eq!(items.iter().sum::<i32>(), 6);
+        assert_eq!(stack.pop_all(), vec![3, 2, 1, 0]);
+    }
+
+    #[test]
+    fn drain_to_any_collection() {
+        use std::collections::{HashSet, VecDeque};
+
+        let stack: ConcurrentStack<i32> = (0..10).chain(0..5).collect();
+        let mut set = HashSet::new();
+        stack.drain_to(&mut set);
+        assert_eq!(set, (0..10).collect());
+        assert!(stack.is_empty());
+
+        stack.push_iter(0..3);
+        let mut queue = VecDeque::from(vec![9]);
+        stack.drain_to(&mut queue);
+        assert_eq!(queue, [9, 2, 1, 0]);
+
+        let units: ConcurrentStack<()> = (0..3).map(|_| ()).collect();
+        let mut out = vec![];
+        units.drain_to(&mut out);
+        assert_eq!(out.len(), 3);
+        assert!(units.is_empty());
+    }
+
+    #[test]
+    fn take_chain_empties_stack() {
+        let stack: ConcurrentStack<String> = (0..5).map(|i| i.to_string()).collect();
+        let mut chain = stack.take_chain();
+        assert!(stack.is_empty());
+        assert_eq!(stack.pop(), None);
+        assert_eq!(chain.len(), 5);
+        assert_eq!(chain.next().as_deref(), Some("4"));
+        // The chain outlives the stack, and frees what is left when dropped.
+        stack.push("later".to_string());
+        drop(stack);
+        assert_eq!(chain.collect::<Vec<_>>(), ["3", "2", "1", "0"]);
+
+        let units: ConcurrentStack<()> = (0..3).map(|_| ()).collect();
+        assert_eq!(units.take_chain().count(), 3);
+        assert!(units.is_empty());
+        assert_eq!(ConcurrentStack::<i32>::new().take_chain().next(), None);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn cycle_is_detected() {
+        use std::panic::{catch_unwind, AssertUnwindSafe};
+
+        let stack: ConcurrentStack<i32> = (0..3).collect();
+        let top = stack.top.load().0;
+        let bottom = unsafe { (*(*top).next.load(Ordering::Relaxed)).next.load(Ordering::Relaxed) };
+        ConcurrentStack::<i32>::assert_acyclic(top);
+        // Link the bottom back to the top, as a broken relink would.
+        unsafe {
+            (*bottom).next.store(top, Ordering::Relaxed);
+        }
+        let found = catch_unwind(AssertUnwindSafe(|| ConcurrentStack::<i32>::assert_acyclic(top)));
+        let msg = found.unwrap_err().downcast::<&str>().unwrap();
+        assert!(msg.contains("cycle"));
+        // Splicing it onto a stack is caught before it reaches the stack.
+        let other = ConcurrentStack::new();
+        assert!(catch_unwind(AssertUnwindSafe(|| other.splice(top, bottom))).is_err());
+        assert!(other.is_empty());
+        unsafe {
+            (*bottom).next.store(core::ptr::null_mut(), Ordering::Relaxed);
+        }
+        assert_eq!(stack.pop_all(), [2, 1, 0]);
+    }
+
+    #[test]
+    fn append_to_itself() {
+        let stack: ConcurrentStack<i32> = (0..3).collect();
+        stack.append(&stack);
+        assert_eq!(stack.len(), 3);
+        stack.push(3);
+        assert_eq!(stack.pop_all(), vec![3, 2, 1, 0]);
+        assert_eq!(stack.pop(), None);
+    }
+
+    #[test]
+    fn retain_keeps_order() {
+        let mut stack: ConcurrentStack<i32> = (0..10).collect();
+        stack.retain(|v| v % 2 == 0);
+        assert_eq!(stack.len(), 5);
+        stack.push(10);
+        assert_eq!(stack.pop_all(), vec![10, 8, 6, 4, 2, 0]);
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let mut counted: ConcurrentStack<Counted> = (0..4).map(|_| Counted(drops.clone())).collect();
+        let mut i = 0;
+        counted.retain(|_| {
+            i += 1;
+            i == 1
+        });
+        assert_eq!(drops.load(Ordering::SeqCst), 3);
+        assert_eq!(counted.len(), 1);
+        counted.retain(|_| false);
+        assert!(counted.is_empty());
+        assert_eq!(drops.load(Ordering::SeqCst), 4);
+
+        let mut units: ConcurrentStack<()> = (0..4).map(|_| ()).collect();
+        let mut i = 0;
+        units.retain(|_| {
+            i += 1;
+            i % 2 == 0
+        });
+        assert_eq!(units.len(), 2);
+    }
+
+    #[test]
+    fn pop_n_bounded() {
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        assert_eq!(stack.pop_n(0), Vec::<i32>::new());
+        assert_eq!(stack.pop_n(2), vec![4, 3]);
+        assert_eq!(stack.len(), 3);
+        assert_eq!(stack.pop_n(10), vec![2, 1, 0]);
+        assert!(stack.is_empty());
+        assert_eq!(stack.pop_n(1), Vec::<i32>::new());
+    }
+
+    #[test]
+    fn pop_batch_into_reuses_buffer() {
+        let stack: ConcurrentStack<i32> = (0..10).collect();
+        let mut buf = Vec::with_capacity(4);
+        let mut all = Vec::new();
+        loop {
+            buf.clear();
+            let n = stack.pop_batch_into(&mut buf, 4);
+            assert_eq!(n, buf.len());
+            assert_eq!(buf.capacity(), 4);
+            if n == 0 {
+                break;
+            }
+            all.extend_from_slice(&buf);
+        }
+        assert_eq!(all, (0..10).rev().collect::<Vec<_>>());
+
+        let mut buf = vec![-1];
+        stack.push_iter(0..2);
+        assert_eq!(stack.pop_batch_into(&mut buf, 0), 0);
+        assert_eq!(stack.pop_batch_into(&mut buf, 5), 2);
+        assert_eq!(buf, vec![-1, 1, 0]);
+    }
+
+    #[test]
+    fn steal_half_leaves_rest() {
+        let stack: ConcurrentStack<i32> = (0..10).collect();
+        assert_eq!(stack.steal_half(), [9, 8, 7, 6, 5]);
+        assert_eq!(stack.len(), 5);
+        assert_eq!(stack.steal_half(), [4, 3, 2]);
+        assert_eq!(stack.steal_half(), [1]);
+        assert_eq!(stack.steal_half(), [0]);
+        assert_eq!(stack.steal_half(), []);
+
+        // Thieves racing the owner never duplicate or lose values.
+        let stack = Arc::new((0..1000).collect::<ConcurrentStack<i32>>());
+        let thieves = (0..3)
+                          .map(|_| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut stolen = vec![];
+                                  while !stack.is_empty() {
+                                      stolen.extend(stack.steal_half());
+                                  }
+                                  stolen
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let mut all = std::iter::from_fn(|| stack.pop()).collect::<Vec<_>>();
+        for t in thieves {
+            all.extend(t.join().unwrap());
+        }
+        all.sort();
+        assert_eq!(all, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pop_exact_all_or_nothing() {
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        assert_eq!(stack.pop_exact(6), None);
+        assert_eq!(stack.len(), 5);
+        assert_eq!(stack.pop_exact(2), Some(vec![4, 3]));
+        assert_eq!(stack.pop_exact(3), Some(vec![2, 1, 0]));
+        assert_eq!(stack.pop_exact(1), None);
+        assert_eq!(stack.pop_exact(0), Some(vec![]));
+
+        stack.push_iter(0..3);
+        assert_eq!(stack.pop_exact(4), None);
+        assert_eq!(stack.pop_all(), [2, 1, 0]);
+
+        let units: ConcurrentStack<()> = (0..3).map(|_| ()).collect();
+        assert_eq!(units.pop_exact(4), None);
+        assert_eq!(units.pop_exact(3).map(|v| v.len()), Some(3));
+        assert!(units.is_empty());
+
+        // Batches taken concurrently are always whole.
+        let stack = Arc::new(ConcurrentStack::new());
+        let workers = (0..4)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut taken = vec![];
+                                  for i in 0..200 {
+                                      stack.push(t * 200 + i);
+                                      if let Some(batch) = stack.pop_exact(3) {
+                                          assert_eq!(batch.len(), 3);
+                                          taken.extend(batch);
+                                      }
+                                  }
+                                  taken
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let mut all = stack.pop_all();
+        for w in workers {
+            all.extend(w.join().unwrap());
+        }
+        all.extend(stack.pop_all());
+        all.sort();
+        assert_eq!(all, (0..800).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn try_pop_n_single_attempt() {
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        assert_eq!(stack.try_pop_n(5), vec![4, 3, 2, 1, 0]);
+        assert!(stack.is_empty());
+        assert_eq!(stack.try_pop_n(3), Vec::<i32>::new());
+        stack.push_iter(0..2);
+        assert_eq!(stack.try_pop_n(3), vec![1, 0]);
+        assert_eq!(stack.len(), 0);
+        stack.push(7);
+        assert_eq!(stack.try_pop_n(0), Vec::<i32>::new());
+        assert_eq!(stack.len(), 1);
+    }
+
+    #[test]
+    fn try_pop_n_contended() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..4)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut got = Vec::new();
+                                  for i in 0..1000 {
+                                      stack.push(t * 1000 + i);
+                                      got.extend(stack.try_pop_n(2));
+                                  }
+                                  got
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let mut all = stack.pop_all();
+        for t in threads {
+            all.extend(t.join().unwrap());
+        }
+        all.extend(stack.pop_all());
+        all.sort();
+        assert_eq!(all, (0..4000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn pop_n_contended() {
+        let stack = Arc::new(ConcurrentStack::new());
+        let threads = (0..4)
+                          .map(|t| {
+                              let stack = stack.clone();
+                              thread::spawn(move || {
+                                  let mut got = Vec::new();
+                                  for i in 0..1000 {
+                                      stack.push(t * 1000 + i);
+                                      if i % 4 == 3 {
+                                          got.extend(stack.pop_n(3));
+                                      }
+                                  }
+                                  got
+                              })
+                          })
+                          .collect::<Vec<_>>();
+        let mut all = Vec::new();
+        for t in threads {
+            all.extend(t.join().unwrap());
+        }
+        all.extend(stack.pop_all());
+        all.sort();
+        assert_eq!(all, (0..4000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn observer_sees_push_pop() {
+        use super::Observer;
+        use std::sync::Mutex;
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+
+        impl Observer for Arc<Recorder> {
+            fn on_push(&self, n: usize) {
+                self.0.lock().unwrap().push(format!("push {}", n));
+            }
+
+            fn on_pop(&self, n: usize) {
+                self.0.lock().unwrap().push(format!("pop {}", n));
+            }
+
+            fn on_cas_retry(&self) {
+                self.0.lock().unwrap().push("retry".to_string());
+            }
+
+            fn on_node_alloc(&self) {
+                self.0.lock().unwrap().push("alloc".to_string());
+            }
+
+            fn on_node_recycle(&self) {
+                self.0.lock().unwrap().push("recycle".to_string());
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let stack = ConcurrentStack::with_observer(recorder.clone());
+        stack.push(1);
+        assert_eq!(stack.pop(), Some(1));
+        stack.push_iter(0..2);
+        let mut expected = vec!["alloc", "push 1", "pop 1"];
+        if super::RECYCLE {
+            expected.extend(["recycle", "alloc", "push 2"]);
+        } else {
+            expected.extend(["alloc", "alloc", "push 2"]);
+        }
+        assert_eq!(*recorder.0.lock().unwrap(), expected);
+
+        // A clone reports to the same observer.
+        recorder.0.lock().unwrap().clear();
+        stack.clone().pop();
+        assert!(recorder.0.lock().unwrap().contains(&"pop 1".to_string()));
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn stats_count_operations() {
+        let stack = ConcurrentStack::with_capacity(4);
+        stack.push(1);
+        stack.push_iter(vec![2, 3]);
+        assert_eq!(stack.try_push(4), Ok(()));
+        assert_eq!(stack.try_push(5), Err(5));
+        assert_eq!(stack.pop(), Some(4));
+        assert_eq!(stack.pop_if(|v| *v == 0), None);
+        assert_eq!(stack.pop_n(2), vec![3, 2]);
+        let stats = stack.stats();
+        assert_eq!(stats.pushes, 4);
+        assert_eq!(stats.pops, 3);
+        assert_eq!(stats.cas_retries, 0);
+        assert_eq!(stats.eliminations, 0);
+        assert_eq!(stats.len, 1);
+        assert_eq!(stats.trash_len, if super::RECYCLE { 3 } else { 0 });
+        stack.clear();
+        assert_eq!(stack.stats().pops, 4);
+    }
+
+    #[test]
+    fn pop_all_in_pop_order() {
+        let stack: ConcurrentStack<i32> = (0..5).collect();
+        assert_eq!(stack.pop_all(), vec![4, 3, 2, 1, 0]);
+        assert!(stack.is_empty());
+        assert_eq!(stack.len(), 0);
+        assert_eq!(stack.pop_all(), vec![]);
+    }
+}
+
+#[cfg(all(test, loom))]
+mod loom_tests {
+    use super::{ConcurrentStack, EpochReclaim};
+    use loom::sync::Arc;
+    use loom::thread;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Counts its drops, to catch values dropped twice or never.
+    struct Counted(usize, std::sync::Arc<AtomicUsize>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.1.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn concurrent_pushes() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::new());
+            let threads = (0..2)
+                              .map(|i| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || stack.push(i))
+                              })
+                              .collect::<Vec<_>>();
+            for t in threads {
+                t.join().unwrap();
+            }
+            let mut values = stack.pop_all();
+            values.sort();
+            assert_eq!(values, vec![0, 1]);
+        });
+    }
+
+    #[test]
+    fn concurrent_pops() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::from_vec(vec![0, 1]));
+            let threads = (0..2)
+                              .map(|_| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || stack.pop())
+                              })
+                              .collect::<Vec<_>>();
+            let mut values = threads.into_iter().map(|t| t.join().unwrap().unwrap()).collect::<Vec<_>>();
+            values.sort();
+            assert_eq!(values, vec![0, 1]);
+            assert!(stack.is_empty());
+        });
+    }
+
+    // Pops put nodes in the trash that pushes take out again, with the ABA chances
+    // that come with it.
+    #[test]
+    fn push_pop_recycling() {
+        loom::model(|| {
+            let drops = std::sync::Arc::new(AtomicUsize::new(0));
+            let stack = Arc::new(ConcurrentStack::new());
+            stack.push(Counted(0, drops.clone()));
+            let threads = (1..3)
+                              .map(|i| {
+                                  let stack = stack.clone();
+                                  let drops = drops.clone();
+                                  thread::spawn(move || {
+                                      let v = stack.pop();
+                                      stack.push(Counted(i, drops));
+                                      v.map(|v| v.0)
+                                  })
+                              })
+                              .collect::<Vec<_>>();
+            let mut values = threads.into_iter().filter_map(|t| t.join().unwrap()).collect::<Vec<_>>();
+            values.extend(stack.pop_all().iter().map(|v| v.0));
+            values.sort();
+            // Every value came out exactly once, and was dropped exactly once.
+            assert_eq!(values, vec![0, 1, 2]);
+            assert_eq!(drops.load(Ordering::SeqCst), 3);
+        });
+    }
+
+    #[test]
+    fn peek_while_popping() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::from_vec(vec![String::from("a")]));
+            let popper = {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            };
+            if let Some(v) = stack.peek() {
+                assert_eq!(v, "a");
+            }
+            assert_eq!(popper.join().unwrap().as_deref(), Some("a"));
+        });
+    }
+
+    // The node of "x" goes to the trash and is reused for "y", a popper must see "y"
+    // and never the old value, or the `None` it left behind.
+    #[test]
+    fn recycled_node_publishes_value() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::with_trash_limit(1));
+            stack.push(String::from("x"));
+            assert_eq!(stack.pop().as_deref(), Some("x"));
+            assert_eq!(stack.trash_len(), 1);
+            let popper = {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            };
+            stack.push(String::from("y"));
+            let popped = popper.join().unwrap().into_iter().chain(stack.pop());
+            assert_eq!(popped.collect::<Vec<_>>(), vec!["y"]);
+        });
+    }
+
+    #[test]
+    fn push_if_empty_race() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::new());
+            let threads = (0..2)
+                              .map(|i| {
+                                  let stack = stack.clone();
+                                  thread::spawn(move || stack.push_if_empty(i).is_ok())
+                              })
+                              .collect::<Vec<_>>();
+            let won = threads.into_iter().map(|t| t.join().unwrap()).filter(|&ok| ok).count();
+            assert_eq!(won, 1);
+            assert_eq!(stack.pop_all().len(), 1);
+        });
+    }
+
+    #[test]
+    fn epoch_peek_while_popping() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::<String, EpochReclaim>::with_reclaim());
+            stack.push(String::from("a"));
+            let popper = {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            };
+            if let Some(v) = stack.peek() {
+                assert_eq!(v, "a");
+            }
+            assert_eq!(popper.join().unwrap().as_deref(), Some("a"));
+        });
+    }
+
+    // While one thread detaches a node and moves its value out, another peeks at the
+    // same node and then pushes, possibly getting the node back from the trash. Loom
+    // tracks every access to `data`, so any overlap of the move with the clone or the
+    // new value would fail the model.
+    #[test]
+    fn popped_node_owned_until_trashed() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::with_trash_limit(1));
+            stack.push(String::from("a"));
+            let popper = {
+                let stack = stack.clone();
+                thread::spawn(move || stack.pop())
+            };
+            if let Some(v) = stack.peek() {
+                assert_eq!(v, "a");
+            }
+            stack.push(String::from("b"));
+            let mut popped = popper.join().unwrap().into_iter().chain(stack.pop_all()).collect::<Vec<_>>();
+            popped.sort();
+            assert_eq!(popped, vec!["a", "b"]);
+        });
+    }
+
+    // A value pushed on one thread is read complete by the thread popping it.
+    #[test]
+    fn push_publishes_value() {
+        loom::model(|| {
+            let stack = Arc::new(ConcurrentStack::new());
+            let pusher = {
+                let stack = stack.clone();
+                thread::spawn(move || {
+                    stack.push(vec![1, 2]);
+                    stack.push(vec![3, 4]);
+                })
+            };
+            let mut seen = Vec::new();
+            while seen.len() < 2 {
+                if let Some(v) = stack.pop() {
+                    seen.push(v);
+                } else {
+                    thread::yield_now();
+                }
+            }
+            pusher.join().unwrap();
+            seen.sort();
+            assert_eq!(seen, vec![vec![1, 2], vec![3, 4]]);
+        });
     }
 }
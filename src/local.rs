@@ -0,0 +1,60 @@
+//! Per thread cache of free nodes, enabled by the `std` feature.
+//!
+//! A node only goes into the cache once no other thread can reach it any more, the
+//! same condition under which it could be freed. So the cache owns its nodes outright:
+//! they are plain memory of a given layout, usable by any stack whose nodes have that
+//! layout, and are freed when the thread exits.
+
+use std::alloc::{dealloc, Layout};
+use std::cell::RefCell;
+
+/// Most nodes one thread keeps, over all stacks.
+pub const CAPACITY: usize = 8;
+
+struct Cache(Vec<(Layout, *mut u8)>);
+
+impl Drop for Cache {
+    fn drop(&mut self) {
+        for &(layout, p) in &self.0 {
+            unsafe {
+                dealloc(p, layout);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static CACHE: RefCell<Cache> = const { RefCell::new(Cache(Vec::new())) };
+}
+
+/// Keep the node `p` of `layout`, which holds nothing that needs dropping, or return
+/// false if the cache is full. The cache is gone while the thread exits, and then
+/// takes nothing.
+pub fn put(layout: Layout, p: *mut u8) -> bool {
+    CACHE.try_with(|c| {
+             let mut c = c.borrow_mut();
+             if c.0.len() >= CAPACITY {
+                 return false;
+             }
+             c.0.push((layout, p));
+             true
+         })
+         .unwrap_or(false)
+}
+
+/// Take a node of `layout`, or null if there is none.
+pub fn take(layout: Layout) -> *mut u8 {
+    CACHE.try_with(|c| {
+             let mut c = c.borrow_mut();
+             match c.0.iter().rposition(|&(l, _)| l == layout) {
+                 Some(i) => c.0.swap_remove(i).1,
+                 None => core::ptr::null_mut(),
+             }
+         })
+         .unwrap_or(core::ptr::null_mut())
+}
+
+/// How many more nodes the cache takes.
+pub fn room() -> usize {
+    CACHE.try_with(|c| CAPACITY - c.borrow().0.len()).unwrap_or(0)
+}
@@ -0,0 +1,57 @@
+//! Where a stack allocates its nodes.
+//!
+//! Nodes come from the global allocator. With the `allocator_api` feature, which needs
+//! a nightly compiler, a stack may take any `Allocator` instead, see
+//! `ConcurrentStack::new_in`. Either way the stack only goes through `NodeAlloc`.
+
+use core::alloc::Layout;
+#[cfg(feature = "allocator_api")]
+use core::ptr::NonNull;
+
+#[cfg(feature = "allocator_api")]
+pub use alloc::alloc::{Allocator, Global};
+
+/// What a stack needs from its allocator. Not nameable outside the crate, so without
+/// the `allocator_api` feature the global allocator is the only one.
+pub trait NodeAlloc: Send + Sync {
+    /// Whether this is the global allocator, which a backend may free with on its own.
+    /// Only known without the `allocator_api` feature.
+    const GLOBAL: bool = false;
+
+    /// Allocate a block for `layout`, or return null when out of memory.
+    fn alloc(&self, layout: Layout) -> *mut u8;
+    /// Free the block `p` allocated by `alloc` with the same `layout`.
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout);
+}
+
+/// The global allocator, what `alloc::alloc::Global` stands for on stable.
+#[cfg(not(feature = "allocator_api"))]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Global;
+
+#[cfg(not(feature = "allocator_api"))]
+impl NodeAlloc for Global {
+    const GLOBAL: bool = true;
+
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { alloc::alloc::alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        alloc::alloc::dealloc(p, layout)
+    }
+}
+
+#[cfg(feature = "allocator_api")]
+impl<A: Allocator + Send + Sync> NodeAlloc for A {
+    fn alloc(&self, layout: Layout) -> *mut u8 {
+        match self.allocate(layout) {
+            Ok(p) => p.as_ptr() as *mut u8,
+            Err(_) => core::ptr::null_mut(),
+        }
+    }
+
+    unsafe fn dealloc(&self, p: *mut u8, layout: Layout) {
+        self.deallocate(NonNull::new_unchecked(p), layout)
+    }
+}
@@ -0,0 +1,23 @@
+//! Cache line padding.
+
+use core::ops::Deref;
+
+/// Gives `T` a cache line of its own, so that writes to neighbouring fields do not
+/// invalidate it in other cores' caches. The `no-padding` feature turns it into a plain
+/// wrapper, only to measure what the padding buys.
+#[cfg_attr(not(feature = "no-padding"), repr(align(64)))]
+pub struct CachePadded<T>(T);
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        CachePadded(value)
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
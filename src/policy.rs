@@ -0,0 +1,125 @@
+//! Pluggable waiting between CAS retries, and pooling of popped nodes.
+//!
+//! By default a contended operation spins for a growing number of iterations and then
+//! yields the thread. `ConcurrentStack::with_policy` replaces that with a `RetryPolicy`,
+//! called after every failed CAS of a push or pop.
+//!
+//! Popped nodes are kept for reuse up to the trash limit by default.
+//! `ConcurrentStack::with_recycle_policy` replaces that with a `RecyclePolicy`, asked
+//! for every popped node.
+
+#[cfg(feature = "std")]
+use std::thread;
+#[cfg(feature = "std")]
+use std::time::Duration;
+use sync::spin_loop;
+
+// A `SpinPolicy` spins 2^MAX_SPIN_LIMIT iterations per retry at most.
+const MAX_SPIN_LIMIT: u32 = 16;
+
+/// Decides how a stack operation waits before retrying a CAS it lost.
+///
+/// One policy is shared by all threads using the stack, so it is called through `&self`
+/// and gets the number of failed attempts of the current operation instead of keeping
+/// state of its own.
+pub trait RetryPolicy: Send + Sync {
+    /// Called after the CAS of an operation failed for the `attempt`-th time, counting
+    /// from 0. The operation retries as soon as this returns.
+    fn retry(&self, attempt: u32);
+}
+
+/// Spin for a number of iterations growing with every attempt, up to `2^limit`.
+///
+/// Never gives up the CPU, which keeps latency lowest when contention is short lived
+/// and every thread has a core of its own.
+#[derive(Debug, Clone, Copy)]
+pub struct SpinPolicy {
+    limit: u32,
+}
+
+impl SpinPolicy {
+    /// Spin at most `2^limit` iterations per retry. A `limit` above 16 is taken as 16,
+    /// spinning longer than that only burns the CPU another thread could use.
+    pub fn new(limit: u32) -> Self {
+        SpinPolicy { limit: limit.min(MAX_SPIN_LIMIT) }
+    }
+}
+
+impl Default for SpinPolicy {
+    fn default() -> Self {
+        SpinPolicy::new(6)
+    }
+}
+
+impl RetryPolicy for SpinPolicy {
+    fn retry(&self, attempt: u32) {
+        for _ in 0..1u64 << attempt.min(self.limit) {
+            spin_loop();
+        }
+    }
+}
+
+/// Yield the thread on every retry.
+///
+/// Lets other threads, possibly the one holding up the CAS, run first, which is fairer
+/// when there are more threads than cores.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct YieldPolicy;
+
+#[cfg(feature = "std")]
+impl RetryPolicy for YieldPolicy {
+    fn retry(&self, _attempt: u32) {
+        thread::yield_now();
+    }
+}
+
+/// Spin for the first few retries, then park the thread for a while.
+///
+/// Nothing unparks a waiting thread, it sleeps for the whole timeout, so this trades
+/// latency for CPU time under heavy contention.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy)]
+pub struct ParkPolicy {
+    spins: u32,
+    timeout: Duration,
+}
+
+#[cfg(feature = "std")]
+impl ParkPolicy {
+    /// Spin for the first `spins` retries, then park for `timeout` on every further one.
+    pub fn new(spins: u32, timeout: Duration) -> Self {
+        ParkPolicy { spins, timeout }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for ParkPolicy {
+    fn default() -> Self {
+        ParkPolicy::new(4, Duration::from_micros(50))
+    }
+}
+
+#[cfg(feature = "std")]
+impl RetryPolicy for ParkPolicy {
+    fn retry(&self, attempt: u32) {
+        if attempt < self.spins {
+            for _ in 0..1u64 << attempt.min(6) {
+                spin_loop();
+            }
+        } else {
+            thread::park_timeout(self.timeout);
+        }
+    }
+}
+
+/// Decides whether a popped node is kept in the trash for reuse, or freed.
+///
+/// Like `RetryPolicy` it is shared by all threads using the stack. The trash length it
+/// gets is read with `Relaxed` and may be off by what other threads are recycling at
+/// the same time, so a high watermark is only kept approximately.
+pub trait RecyclePolicy: Send + Sync {
+    /// Called for every popped node with the number of nodes already in the trash.
+    /// Return true to recycle the node, false to free it.
+    fn should_recycle(&self, trash_len: usize) -> bool;
+}
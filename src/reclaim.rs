@@ -0,0 +1,265 @@
+//! Choice of how a stack keeps detached nodes from being freed under a reader.
+//!
+//! Nodes are recycled through the trash and the list heads carry a stamp against ABA
+//! whatever the backend, which only decides when a detached node is no longer read by
+//! another thread: when its value may be moved out and when it may be freed. The one
+//! exception is `CrossbeamReclaim`, which hands nodes to `crossbeam-epoch` to free,
+//! and in exchange does not let values be read in place, see `SharedRead`.
+
+use core::alloc::Layout;
+use epoch::{Epochs, Pin};
+use hazard::{Hazard, Hazards, Walk};
+use sync::spin_loop;
+
+/// A reclamation backend for `ConcurrentStack`, see `HazardReclaim` and `EpochReclaim`.
+///
+/// The trait is sealed, the stack relies on the backends being correct for memory
+/// safety.
+pub trait Reclaim: Backend {}
+
+/// A backend under which a value may be read in place while another thread pops it,
+/// the pop waiting for the read to end. Needed by `peek`, `iter_snapshot` and the
+/// other methods reading values they do not take.
+///
+/// Sealed like `Reclaim`, every backend but `CrossbeamReclaim` implements it.
+pub trait SharedRead: Reclaim {}
+
+/// What a stack needs from its backend. Not nameable outside the crate, which seals
+/// `Reclaim`.
+pub trait Backend: Send + Sync + Sized {
+    /// Held while dereferencing nodes loaded from a list head.
+    type Guard<'a>: Guard
+    where
+        Self: 'a;
+    /// Held while following `next` links, which cannot be validated.
+    type Walk<'a>
+    where
+        Self: 'a;
+
+    /// A backend for a new stack, usable in a `const fn`.
+    #[cfg(not(loom))]
+    const NEW: Self;
+    #[cfg(loom)]
+    fn new() -> Self;
+    fn acquire(&self) -> Self::Guard<'_>;
+    fn walk(&self) -> Self::Walk<'_>;
+    /// Wait until every traversal started so far has ended.
+    fn wait_walkers(&self);
+    /// Wait until no other thread reads the detached node `p`.
+    fn wait_readers<N>(&self, p: *mut N);
+    /// Wait until no other thread reads any of the detached `nodes`, at least one.
+    fn wait_readers_all<N, I: Iterator<Item = *mut N>>(&self, nodes: I) {
+        for p in nodes {
+            self.wait_readers(p);
+        }
+    }
+    /// Check if the detached node `p` can be freed right now.
+    fn may_free<N>(&self, p: *mut N) -> bool;
+    /// Have the detached node `p` of `layout`, without a value and allocated by the
+    /// global allocator, freed once no other thread reads it. Return false for a backend
+    /// that cannot, the node is then recycled or freed as usual.
+    fn defer_free(&self, _p: *mut u8, _layout: Layout) -> bool {
+        false
+    }
+}
+
+pub trait Guard {
+    /// Announce that `p` is about to be dereferenced. It must then be checked to still
+    /// be on the list it was loaded from.
+    fn protect<N>(&self, p: *mut N);
+}
+
+/// Hazard pointers, the default.
+///
+/// Readers publish each node they dereference, so a detach only waits for the threads
+/// reading that very node. Traversals deeper than the top still hold up all frees.
+pub struct HazardReclaim(Hazards);
+
+impl Reclaim for HazardReclaim {}
+
+impl SharedRead for HazardReclaim {}
+
+impl Backend for HazardReclaim {
+    type Guard<'a> = Hazard<'a>;
+    type Walk<'a> = Walk<'a>;
+
+    #[cfg(not(loom))]
+    const NEW: Self = HazardReclaim(Hazards::new());
+    #[cfg(loom)]
+    fn new() -> Self {
+        HazardReclaim(Hazards::new())
+    }
+
+    fn acquire(&self) -> Hazard<'_> {
+        self.0.acquire()
+    }
+
+    fn walk(&self) -> Walk<'_> {
+        self.0.walk()
+    }
+
+    fn wait_walkers(&self) {
+        self.0.wait_walkers()
+    }
+
+    fn wait_readers<N>(&self, p: *mut N) {
+        while self.0.is_protected(p) {
+            spin_loop();
+        }
+    }
+
+    fn may_free<N>(&self, p: *mut N) -> bool {
+        self.0.may_free(p)
+    }
+}
+
+impl<'a> Guard for Hazard<'a> {
+    fn protect<N>(&self, p: *mut N) {
+        Hazard::protect(self, p)
+    }
+}
+
+/// Epoch based reclamation.
+///
+/// Readers pin the stack once per operation instead of publishing every node, which
+/// is cheaper for traversals. In exchange a detach waits for every operation that was
+/// running when it happened, not only for those reading the detached node: a pop
+/// blocks until every concurrent reader, like a `peek` or `iter_snapshot`, is done,
+/// so pops are not lock-free with this backend. Operations detaching many nodes at
+/// once, like `pop_n` or `take_chain`, wait once for the whole run.
+pub struct EpochReclaim(Epochs);
+
+impl Reclaim for EpochReclaim {}
+
+impl SharedRead for EpochReclaim {}
+
+impl Backend for EpochReclaim {
+    type Guard<'a> = Pin<'a>;
+    type Walk<'a> = Pin<'a>;
+
+    #[cfg(not(loom))]
+    const NEW: Self = EpochReclaim(Epochs::new());
+    #[cfg(loom)]
+    fn new() -> Self {
+        EpochReclaim(Epochs::new())
+    }
+
+    fn acquire(&self) -> Pin<'_> {
+        self.0.pin()
+    }
+
+    fn walk(&self) -> Pin<'_> {
+        self.0.pin()
+    }
+
+    fn wait_walkers(&self) {
+        self.0.synchronize()
+    }
+
+    fn wait_readers<N>(&self, _p: *mut N) {
+        self.0.synchronize()
+    }
+
+    // Whoever was running when the last of them was detached has ended.
+    fn wait_readers_all<N, I: Iterator<Item = *mut N>>(&self, _nodes: I) {
+        self.0.synchronize()
+    }
+
+    fn may_free<N>(&self, _p: *mut N) -> bool {
+        !self.0.is_pinned(self.0.advance())
+    }
+}
+
+impl<'a> Guard for Pin<'a> {
+    fn protect<N>(&self, _p: *mut N) {}
+}
+
+/// Epoch based reclamation through the global collector of `crossbeam-epoch`, enabled by
+/// the `crossbeam-epoch` feature.
+///
+/// Readers pin the collector like with `EpochReclaim`, but popped nodes are not
+/// recycled: each is handed to the collector with `Guard::defer` and freed once no
+/// thread pinned before can still read it, so pushes always allocate. A pop moves the
+/// value out right away and never waits for other threads. That is only sound as no
+/// other thread reads values in place, so this backend is not `SharedRead`: `peek`,
+/// `iter_snapshot` and the like are not available with it.
+///
+/// Operations also pin the stack like with `EpochReclaim`, for `take_chain`, `append`
+/// and `split_off` alone: they hand the detached nodes to an owner that frees them
+/// without the collector, so they wait for the operations of this stack that were
+/// running when they detached them. That wait never includes the collector, so it
+/// ends even if the calling thread holds a `crossbeam_epoch::Guard` of its own.
+///
+/// Nodes are only deferred with the global allocator. With the `allocator_api` feature
+/// they are recycled like with the other backends, and freed when the stack is dropped.
+#[cfg(feature = "crossbeam-epoch")]
+pub struct CrossbeamReclaim(Epochs);
+
+#[cfg(feature = "crossbeam-epoch")]
+impl Reclaim for CrossbeamReclaim {}
+
+/// An operation pinning both the collector and the stack.
+#[cfg(feature = "crossbeam-epoch")]
+pub struct CrossbeamPin<'a> {
+    _guard: crossbeam_epoch::Guard,
+    _pin: Pin<'a>,
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+impl CrossbeamReclaim {
+    fn pin(&self) -> CrossbeamPin<'_> {
+        CrossbeamPin { _guard: crossbeam_epoch::pin(), _pin: self.0.pin() }
+    }
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+impl Backend for CrossbeamReclaim {
+    type Guard<'a> = CrossbeamPin<'a>;
+    type Walk<'a> = CrossbeamPin<'a>;
+
+    #[cfg(not(loom))]
+    const NEW: Self = CrossbeamReclaim(Epochs::new());
+    #[cfg(loom)]
+    fn new() -> Self {
+        CrossbeamReclaim(Epochs::new())
+    }
+
+    fn acquire(&self) -> CrossbeamPin<'_> {
+        self.pin()
+    }
+
+    fn walk(&self) -> CrossbeamPin<'_> {
+        self.pin()
+    }
+
+    // Covers every operation, including the pops still reading `next` of a node the
+    // chain operations detached.
+    fn wait_walkers(&self) {
+        self.0.synchronize()
+    }
+
+    // Other threads only ever read `next` of a node, never its value, and a popped node
+    // is only freed by the collector.
+    fn wait_readers<N>(&self, _p: *mut N) {}
+
+    // There is no asking the collector, nodes are only freed through `defer_free`.
+    fn may_free<N>(&self, _p: *mut N) -> bool {
+        false
+    }
+
+    fn defer_free(&self, p: *mut u8, layout: Layout) -> bool {
+        // Sent to whichever thread runs the deferred functions, the node is nobody's
+        // any more, and the global allocator frees from any thread.
+        struct Block(*mut u8);
+        unsafe impl Send for Block {}
+
+        let block = Block(p);
+        crossbeam_epoch::pin().defer(move || unsafe { alloc::alloc::dealloc(block.0, layout) });
+        true
+    }
+}
+
+#[cfg(feature = "crossbeam-epoch")]
+impl<'a> Guard for CrossbeamPin<'a> {
+    fn protect<N>(&self, _p: *mut N) {}
+}
@@ -0,0 +1,52 @@
+//! Serde support, enabled by the `serde` feature.
+
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+use serde::de::{Deserialize, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Serialize, Serializer};
+
+use super::{ConcurrentStack, NodeAlloc, Reclaim, SharedRead};
+
+/// Serialized as a sequence from top to bottom.
+///
+/// The values are cloned off the stack with `iter_snapshot` and serialized from there,
+/// so the stack is left as it is: other threads never see it empty, and a value that
+/// fails to serialize, even by panicking, takes nothing with it. Under concurrent pushes
+/// and pops the sequence is only a best-effort view, serialize with exclusive access
+/// for an exact snapshot.
+impl<T: Serialize + Clone + Sync, R: SharedRead, A: NodeAlloc> Serialize
+    for ConcurrentStack<T, R, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter_snapshot())
+    }
+}
+
+/// Deserialized from a sequence from top to bottom, the order `Serialize` writes.
+impl<'de, T: Deserialize<'de>, R: Reclaim, A: NodeAlloc + Default> Deserialize<'de>
+    for ConcurrentStack<T, R, A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(StackVisitor(PhantomData))
+    }
+}
+
+struct StackVisitor<T, R, A>(PhantomData<(T, R, A)>);
+
+impl<'de, T: Deserialize<'de>, R: Reclaim, A: NodeAlloc + Default> Visitor<'de>
+    for StackVisitor<T, R, A> {
+    type Value = ConcurrentStack<T, R, A>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+        let mut values = Vec::new();
+        while let Some(v) = seq.next_element()? {
+            values.push(v);
+        }
+        let stack = ConcurrentStack::default();
+        stack.push_iter(values.into_iter().rev());
+        Ok(stack)
+    }
+}
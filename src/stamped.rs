@@ -0,0 +1,95 @@
+//! Portable stand-in for `atomic_stamped_ptr::AtomicStampedPtr`.
+//!
+//! Used on targets without the double-width CAS the original relies on. A spinlock
+//! guards the pointer and its stamp, so the stack still works there but is not lock
+//! free any more.
+//!
+//! The stamp is 64 bits wide on every target. The original packs a `usize` next to the
+//! pointer, which is plenty on x86_64, but a 32 bit stamp could wrap within minutes of
+//! heavy churn, and since the lock makes the width free here there is no reason to
+//! keep it that narrow.
+
+use core::cell::UnsafeCell;
+use sync::{spin_loop, AtomicBool, Ordering};
+
+pub struct AtomicStampedPtr<T> {
+    lock: AtomicBool,
+    value: UnsafeCell<(*mut T, Stamp)>,
+}
+
+pub type Stamp = u64;
+
+unsafe impl<T> Send for AtomicStampedPtr<T> {}
+unsafe impl<T> Sync for AtomicStampedPtr<T> {}
+
+impl<T> Default for AtomicStampedPtr<T> {
+    fn default() -> Self {
+        AtomicStampedPtr::null()
+    }
+}
+
+impl<T> AtomicStampedPtr<T> {
+    const_fn! {
+        pub fn null() -> Self {
+            AtomicStampedPtr::new(core::ptr::null_mut())
+        }
+    }
+
+    const_fn! {
+        pub fn new(p: *mut T) -> Self {
+            AtomicStampedPtr {
+                lock: AtomicBool::new(false),
+                value: UnsafeCell::new((p, 0)),
+            }
+        }
+    }
+
+    #[cfg(all(test, not(loom)))]
+    pub fn with_stamp(p: *mut T, stamp: Stamp) -> Self {
+        AtomicStampedPtr {
+            lock: AtomicBool::new(false),
+            value: UnsafeCell::new((p, stamp)),
+        }
+    }
+
+    // SeqCst like the `lock cmpxchg16b` of the original, the hazard pointers depend on it.
+    fn locked<R, F: FnOnce(&mut (*mut T, Stamp)) -> R>(&self, f: F) -> R {
+        while self.lock
+                  .compare_exchange_weak(false, true, Ordering::SeqCst, Ordering::Relaxed)
+                  .is_err() {
+            spin_loop();
+        }
+        let r = f(unsafe { &mut *self.value.get() });
+        self.lock.store(false, Ordering::SeqCst);
+        r
+    }
+
+    /// Load the pointer and its stamp.
+    pub fn load(&self) -> (*mut T, Stamp) {
+        self.locked(|v| *v)
+    }
+
+    /// Store `ptr`, bump the stamp and return the old pointer.
+    pub fn swap(&self, ptr: *mut T) -> *mut T {
+        self.locked(|v| {
+            let old = v.0;
+            *v = (ptr, v.1.wrapping_add(1));
+            old
+        })
+    }
+
+    /// Store `ptr` and bump the stamp if the current value is `current`.
+    pub fn compare_exchange(&self,
+                            current: (*mut T, Stamp),
+                            ptr: *mut T)
+                            -> Result<(*mut T, Stamp), (*mut T, Stamp)> {
+        self.locked(|v| {
+            if *v == current {
+                *v = (ptr, current.1.wrapping_add(1));
+                Ok(current)
+            } else {
+                Err(*v)
+            }
+        })
+    }
+}
@@ -0,0 +1,172 @@
+//! Operation counters and observers.
+//!
+//! With the `stats` feature every stack keeps a few counters, updated with relaxed
+//! atomics next to the operations they count. Without it `Stats` only holds the
+//! observer, and without an observer all its methods come down to one check of an
+//! `Option`.
+
+use alloc::sync::Arc;
+#[cfg(feature = "stats")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Callbacks for the life cycle events of a stack, see
+/// `ConcurrentStack::with_observer`.
+///
+/// Every method does nothing by default, so an observer only implements those it
+/// cares about. They are called right on the path of the operation, from whichever
+/// thread runs it, and should be quick.
+pub trait Observer: Send + Sync {
+    /// `n` values were pushed at once, after the CAS publishing them.
+    fn on_push(&self, n: usize) {
+        let _ = n;
+    }
+
+    /// `n` values were popped at once, after the CAS detaching them.
+    fn on_pop(&self, n: usize) {
+        let _ = n;
+    }
+
+    /// A CAS on the stack or trash list lost a race and is retried.
+    fn on_cas_retry(&self) {}
+
+    /// A node was allocated instead of recycled.
+    fn on_node_alloc(&self) {}
+
+    /// A popped node was put in the trash for reuse instead of being freed.
+    fn on_node_recycle(&self) {}
+}
+
+/// A snapshot of the counters of a stack, see `ConcurrentStack::stats`.
+///
+/// The counters are read one at a time, so under concurrent operations they may not
+/// add up exactly.
+#[cfg(feature = "stats")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct StackStats {
+    /// Values pushed so far, a value `pop_if` pushes back is not counted again.
+    pub pushes: u64,
+    /// Values popped so far, including those dropped by `clear`.
+    pub pops: u64,
+    /// Times a CAS on the stack or trash list lost a race and was retried.
+    pub cas_retries: u64,
+    /// Values a pop took straight from a push through the elimination array, without
+    /// either touching the top, see `with_elimination`.
+    pub eliminations: u64,
+    /// Values on the stack, as returned by `len`.
+    pub len: usize,
+    /// Popped nodes kept for reuse.
+    pub trash_len: usize,
+}
+
+// Every thread writes these, keep them off the lines of the other fields.
+#[cfg(feature = "stats")]
+#[repr(align(64))]
+pub struct Stats {
+    pushes: AtomicU64,
+    pops: AtomicU64,
+    cas_retries: AtomicU64,
+    eliminations: AtomicU64,
+    pub observer: Option<Arc<dyn Observer>>,
+}
+
+#[cfg(feature = "stats")]
+impl Stats {
+    pub const fn new() -> Self {
+        Stats {
+            pushes: AtomicU64::new(0),
+            pops: AtomicU64::new(0),
+            cas_retries: AtomicU64::new(0),
+            eliminations: AtomicU64::new(0),
+            observer: None,
+        }
+    }
+
+    pub fn pushed(&self, n: usize) {
+        self.pushes.fetch_add(n as u64, Ordering::Relaxed);
+        if let Some(ref o) = self.observer {
+            o.on_push(n);
+        }
+    }
+
+    pub fn popped(&self, n: usize) {
+        self.pops.fetch_add(n as u64, Ordering::Relaxed);
+        if let Some(ref o) = self.observer {
+            o.on_pop(n);
+        }
+    }
+
+    pub fn retried(&self) {
+        self.cas_retries.fetch_add(1, Ordering::Relaxed);
+        if let Some(ref o) = self.observer {
+            o.on_cas_retry();
+        }
+    }
+
+    pub fn eliminated(&self) {
+        self.eliminations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self, len: usize, trash_len: usize) -> StackStats {
+        StackStats {
+            pushes: self.pushes.load(Ordering::Relaxed),
+            pops: self.pops.load(Ordering::Relaxed),
+            cas_retries: self.cas_retries.load(Ordering::Relaxed),
+            eliminations: self.eliminations.load(Ordering::Relaxed),
+            len,
+            trash_len,
+        }
+    }
+}
+
+#[cfg(not(feature = "stats"))]
+pub struct Stats {
+    pub observer: Option<Arc<dyn Observer>>,
+}
+
+#[cfg(not(feature = "stats"))]
+impl Stats {
+    pub const fn new() -> Self {
+        Stats { observer: None }
+    }
+
+    #[inline(always)]
+    pub fn pushed(&self, n: usize) {
+        if let Some(ref o) = self.observer {
+            o.on_push(n);
+        }
+    }
+
+    #[inline(always)]
+    pub fn popped(&self, n: usize) {
+        if let Some(ref o) = self.observer {
+            o.on_pop(n);
+        }
+    }
+
+    #[inline(always)]
+    pub fn retried(&self) {
+        if let Some(ref o) = self.observer {
+            o.on_cas_retry();
+        }
+    }
+
+    #[inline(always)]
+    pub fn eliminated(&self) {}
+}
+
+impl Stats {
+    #[inline(always)]
+    pub fn allocated(&self) {
+        if let Some(ref o) = self.observer {
+            o.on_node_alloc();
+        }
+    }
+
+    #[inline(always)]
+    pub fn recycled(&self) {
+        if let Some(ref o) = self.observer {
+            o.on_node_recycle();
+        }
+    }
+}
@@ -0,0 +1,43 @@
+//! Atomics used by the stack.
+//!
+//! Built with `--cfg loom` these are loom's instrumented types, so that the model
+//! checker sees every access, otherwise they are the ones from `core`.
+
+#[cfg(loom)]
+pub use loom::hint::spin_loop;
+#[cfg(loom)]
+pub use loom::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(not(loom))]
+pub use core::hint::spin_loop;
+#[cfg(not(loom))]
+pub use core::sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering};
+
+#[cfg(loom)]
+pub use loom::cell::UnsafeCell;
+
+/// The subset of `loom::cell::UnsafeCell` the stack uses, so that loom can check the
+/// accesses to node values too.
+#[cfg(not(loom))]
+pub struct UnsafeCell<T>(core::cell::UnsafeCell<T>);
+
+#[cfg(not(loom))]
+impl<T> UnsafeCell<T> {
+    pub fn new(value: T) -> Self {
+        UnsafeCell(core::cell::UnsafeCell::new(value))
+    }
+
+    #[inline(always)]
+    pub fn with<R, F: FnOnce(*const T) -> R>(&self, f: F) -> R {
+        f(self.0.get())
+    }
+
+    #[inline(always)]
+    pub fn with_mut<R, F: FnOnce(*mut T) -> R>(&self, f: F) -> R {
+        f(self.0.get())
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0.into_inner()
+    }
+}
@@ -0,0 +1,51 @@
+//! Awaiting pops of an `AsyncStack` from tokio tasks.
+
+#![cfg(feature = "std")]
+
+extern crate concurrent_stack;
+extern crate tokio;
+
+use concurrent_stack::AsyncStack;
+use std::future::poll_fn;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
+
+#[test]
+fn task_woken_by_push() {
+    let rt = Runtime::new().unwrap();
+    let stack = Arc::new(AsyncStack::new());
+    let task = {
+        let stack = stack.clone();
+        rt.spawn(poll_fn(move |cx| stack.poll_pop(cx)))
+    };
+    thread::sleep(Duration::from_millis(50));
+    assert!(!task.is_finished());
+    stack.push(42);
+    assert_eq!(rt.block_on(task).unwrap(), 42);
+    assert!(stack.is_empty());
+}
+
+#[test]
+fn pop_async_from_producer_thread() {
+    let rt = Runtime::new().unwrap();
+    let stack = Arc::new(AsyncStack::new());
+    let producer = {
+        let stack = stack.clone();
+        thread::spawn(move || {
+            for i in 0..100 {
+                stack.push(i);
+                if i % 10 == 0 {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        })
+    };
+    let mut sum = 0;
+    for _ in 0..100 {
+        sum += rt.block_on(stack.pop_async());
+    }
+    producer.join().unwrap();
+    assert_eq!(sum, 4950);
+}
@@ -0,0 +1,14 @@
+//! The stack is `Send` and `Sync` only for `T: Send`, check that values which must stay
+//! on their thread cannot leave it through a stack.
+//!
+//! The expected compiler output lives next to each case under `tests/ui`. After a
+//! compiler upgrade changes the wording, refresh it with `TRYBUILD=overwrite`.
+
+extern crate trybuild;
+
+#[test]
+fn send_bounds() {
+    let t = trybuild::TestCases::new();
+    t.compile_fail("tests/ui/stack_of_rc_*.rs");
+    t.pass("tests/ui/send_values_shared.rs");
+}
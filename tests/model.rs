@@ -0,0 +1,80 @@
+//! Random sequences of operations on one thread, checked against a `Vec` as the model
+//! stack. proptest shrinks a failing sequence down to a minimal one.
+
+extern crate concurrent_stack;
+#[macro_use]
+extern crate proptest;
+
+use concurrent_stack::ConcurrentStack;
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+#[derive(Debug, Clone)]
+enum Op {
+    Push(u16),
+    Pop,
+    PopAll,
+    PopN(usize),
+    PushIter(Vec<u16>),
+    Append(Vec<u16>),
+    SplitOff(usize),
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![any::<u16>().prop_map(Op::Push),
+                Just(Op::Pop),
+                Just(Op::PopAll),
+                (0..8usize).prop_map(Op::PopN),
+                vec(any::<u16>(), 0..8).prop_map(Op::PushIter),
+                vec(any::<u16>(), 0..8).prop_map(Op::Append),
+                (0..8usize).prop_map(Op::SplitOff)]
+}
+
+proptest! {
+    #[test]
+    fn matches_vec_model(ops in vec(op(), 0..64)) {
+        let stack = ConcurrentStack::new();
+        let mut model = Vec::new();
+        for op in ops {
+            match op {
+                Op::Push(v) => {
+                    stack.push(v);
+                    model.push(v);
+                }
+                Op::Pop => prop_assert_eq!(stack.pop(), model.pop()),
+                Op::PopAll => {
+                    let expected = model.drain(..).rev().collect::<Vec<_>>();
+                    prop_assert_eq!(stack.pop_all(), expected);
+                }
+                Op::PopN(n) => {
+                    let at = model.len().saturating_sub(n);
+                    let expected = model.split_off(at).into_iter().rev().collect::<Vec<_>>();
+                    prop_assert_eq!(stack.pop_n(n), expected);
+                }
+                Op::PushIter(values) => {
+                    stack.push_iter(values.iter().cloned());
+                    model.extend(values);
+                }
+                Op::Append(values) => {
+                    let other = ConcurrentStack::new();
+                    other.push_iter(values.iter().cloned());
+                    stack.append(&other);
+                    prop_assert!(other.is_empty());
+                    model.extend(values);
+                }
+                Op::SplitOff(at) => {
+                    let split = stack.split_off(at);
+                    let bottom = model.len().saturating_sub(at);
+                    let kept = model.split_off(bottom);
+                    let expected = model.drain(..).rev().collect::<Vec<_>>();
+                    model = kept;
+                    prop_assert_eq!(split.pop_all(), expected);
+                }
+            }
+            prop_assert_eq!(stack.len(), model.len());
+            prop_assert_eq!(stack.is_empty(), model.is_empty());
+        }
+        model.reverse();
+        prop_assert_eq!(stack.pop_all(), model);
+    }
+}
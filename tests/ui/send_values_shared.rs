@@ -0,0 +1,20 @@
+extern crate concurrent_stack;
+
+use concurrent_stack::ConcurrentStack;
+use std::cell::Cell;
+use std::sync::Arc;
+use std::thread;
+
+fn main() {
+    let stack = Arc::new(ConcurrentStack::new());
+    let shared = stack.clone();
+    thread::spawn(move || shared.push(Box::new(1))).join().unwrap();
+    assert_eq!(stack.pop(), Some(Box::new(1)));
+
+    // Values are only ever moved between threads, never shared, so `Send` is enough
+    // for the stack to be `Sync`.
+    let cells = Arc::new(ConcurrentStack::new());
+    let shared = cells.clone();
+    thread::spawn(move || shared.push(Cell::new(1))).join().unwrap();
+    assert_eq!(cells.pop().map(Cell::into_inner), Some(1));
+}
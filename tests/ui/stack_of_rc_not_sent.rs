@@ -0,0 +1,13 @@
+extern crate concurrent_stack;
+
+use concurrent_stack::ConcurrentStack;
+use std::rc::Rc;
+use std::thread;
+
+fn main() {
+    let stack = ConcurrentStack::new();
+    stack.push(Rc::new(1));
+    thread::spawn(move || {
+        stack.pop();
+    });
+}
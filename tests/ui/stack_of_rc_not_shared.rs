@@ -0,0 +1,16 @@
+extern crate concurrent_stack;
+
+use concurrent_stack::ConcurrentStack;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::thread;
+
+// An `Rc` popped on another thread would share its count with clones left here.
+fn main() {
+    let stack = Arc::new(ConcurrentStack::new());
+    stack.push(Rc::new(1));
+    let shared = stack.clone();
+    thread::spawn(move || {
+        shared.pop();
+    });
+}